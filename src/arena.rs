@@ -40,7 +40,7 @@ macro_rules! __arena_index {
 
 pub use __arena_index as arena_index;
 
-use std::mem;
+use std::{collections::VecDeque, mem};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ArenaIndex {
@@ -58,6 +58,28 @@ impl ArenaIndex {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LookupStatus {
+    Live,
+    /// The slot exists but nothing currently occupies it
+    FreedSlot,
+    /// The slot was reused by a newer value after this index's value was removed
+    GenerationMismatch,
+    OutOfRange,
+}
+
+/// Which freed slot an arena reuses when a value is inserted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AllocationPolicy {
+    /// The most recently freed slot
+    #[default]
+    Lifo,
+    /// The least recently freed slot
+    Fifo,
+    /// The freed slot with the lowest index, which keeps values inserted together close together
+    LowestIndex,
+}
+
 enum Entry<T> {
     Free,
     Occupied { generation: u64, value: T },
@@ -65,7 +87,8 @@ enum Entry<T> {
 
 pub struct Arena<T> {
     arena: Vec<Entry<T>>,
-    next_free: Vec<usize>,
+    next_free: VecDeque<usize>,
+    allocation_policy: AllocationPolicy,
     generation: u64,
     count: usize,
 }
@@ -74,7 +97,8 @@ impl<T> Default for Arena<T> {
     fn default() -> Self {
         Self {
             arena: vec![],
-            next_free: vec![],
+            next_free: VecDeque::new(),
+            allocation_policy: AllocationPolicy::default(),
             generation: 0,
             count: 0,
         }
@@ -82,6 +106,13 @@ impl<T> Default for Arena<T> {
 }
 
 impl<T> Arena<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            arena: Vec::with_capacity(capacity),
+            ..Self::default()
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -94,6 +125,37 @@ impl<T> Arena<T> {
         self.arena.len()
     }
 
+    /// How many slots the arena can hold before it has to grow
+    pub fn capacity(&self) -> usize {
+        self.arena.capacity()
+    }
+
+    pub fn allocation_policy(&self) -> AllocationPolicy {
+        self.allocation_policy
+    }
+
+    /// Changes which freed slot is reused by later inserts, including slots freed before the
+    /// change
+    pub fn set_allocation_policy(&mut self, allocation_policy: AllocationPolicy) {
+        self.allocation_policy = allocation_policy;
+    }
+
+    fn take_free_slot(&mut self) -> Option<usize> {
+        match self.allocation_policy {
+            AllocationPolicy::Lifo => self.next_free.pop_back(),
+            AllocationPolicy::Fifo => self.next_free.pop_front(),
+            AllocationPolicy::LowestIndex => {
+                let (position, _index) = self
+                    .next_free
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_position, index)| **index)?;
+
+                self.next_free.remove(position)
+            }
+        }
+    }
+
     pub fn occupied_by_usize(&self) -> impl Iterator<Item = usize> {
         self.arena
             .iter()
@@ -122,7 +184,7 @@ impl<T> Arena<T> {
     pub fn insert(&mut self, value: T) -> ArenaIndex {
         self.count += 1;
 
-        if let Some(free_index) = self.next_free.pop()
+        if let Some(free_index) = self.take_free_slot()
             && let Some(entry) = self.arena.get_mut(free_index)
         {
             *entry = Entry::Occupied {
@@ -147,6 +209,21 @@ impl<T> Arena<T> {
         }
     }
 
+    pub fn contains(&self, index: ArenaIndex) -> bool {
+        matches!(self.lookup_status(index), LookupStatus::Live)
+    }
+
+    pub fn lookup_status(&self, index: ArenaIndex) -> LookupStatus {
+        match self.arena.get(index.index) {
+            None => LookupStatus::OutOfRange,
+            Some(Entry::Free) => LookupStatus::FreedSlot,
+            Some(Entry::Occupied { generation, .. }) if *generation == index.generation => {
+                LookupStatus::Live
+            }
+            Some(Entry::Occupied { .. }) => LookupStatus::GenerationMismatch,
+        }
+    }
+
     pub fn get_index_by_usize(&self, index: usize) -> Option<ArenaIndex> {
         if let Entry::Occupied { generation, .. } = self.arena.get(index)? {
             Some(ArenaIndex {
@@ -202,7 +279,7 @@ impl<T> Arena<T> {
                 panic!("Occupied entry was unexpectedly free")
             };
 
-            self.next_free.push(index);
+            self.next_free.push_back(index);
 
             self.generation += 1;
             self.count -= 1;
@@ -1,9 +1,22 @@
-use crate::arena::{self, Arena};
-use crate::lex::token::Token;
+pub mod builder;
+pub mod condition;
+pub mod edit;
+pub mod offset_index;
+pub mod registry;
+pub mod walk;
+
+use self::{offset_index::OffsetIndex, registry::KeyRegistry, walk::Walk};
+
+use crate::arena::{self, AllocationPolicy, Arena, LookupStatus};
+use crate::lex::token::{SymbolClass, Token, TokenKind};
+use crate::lint::{Lint, LintKind};
+use crate::parse::{Parser, error::ParseError};
+use crate::reporting::{self, Span};
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet, hash_map::DefaultHasher},
     fmt::{self, Write},
+    hash::{Hash, Hasher},
     mem,
     num::ParseFloatError,
 };
@@ -13,6 +26,9 @@ pub struct Data {
     sources: Arena<String>,
     root_nodes: Vec<(SourceIndex, NodeIndex)>,
     error_node: NodeIndex,
+    source_hashes: HashMap<u64, Vec<SourceIndex>>,
+    blank_lines_before: HashMap<NodeIndex, usize>,
+    trailing_trivia: HashMap<SourceIndex, Span>,
 }
 
 pub enum Node {
@@ -26,6 +42,27 @@ pub enum Node {
     Error,
 }
 
+/// Options for `Data::write_with_options` and `Data::write_root_nodes_with_options`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct WriteOptions {
+    /// Writes a `# <parse error here>` comment in place of each `Error` node, instead of
+    /// skipping it
+    pub annotate_errors: bool,
+    /// Writes the `Token::leading_trivia` of each token in place of the usual indentation and
+    /// spacing, and each source's `Data::trailing_trivia` after its last root node, so sources
+    /// parsed with `Parser::capture_trivia` are written back byte for byte
+    ///
+    /// Quotes are part of the trivia, so tokens with trivia are written without adding any.
+    /// Tokens without trivia, such as those added after parsing, are written as usual.
+    pub lossless: bool,
+    /// Writes the children of every node sorted by their leading lexeme, keeping children with
+    /// the same one in their original order
+    ///
+    /// Some blocks, like a mission's conversation, depend on the order of their children, so
+    /// this should only be used on data where order doesn't matter
+    pub sort_children: bool,
+}
+
 arena::arena_index! {
     pub NodeIndex
 }
@@ -34,21 +71,56 @@ arena::arena_index! {
     pub SourceIndex
 }
 
+// each line is one node, and lines of typical data files are a little over 20 bytes long
+const BYTES_PER_NODE: usize = 24;
+
 impl Default for Data {
     fn default() -> Self {
-        let mut nodes = Arena::default();
+        Self::with_arenas(Arena::default(), Arena::default())
+    }
+}
+
+impl Data {
+    // the error node is always the first node, so it's added here rather than by the caller
+    fn with_arenas(mut nodes: Arena<Node>, sources: Arena<String>) -> Self {
         let error_node = nodes.insert(Node::Error).into();
 
         Self {
             nodes,
-            sources: Arena::default(),
+            sources,
             root_nodes: vec![],
             error_node,
+            source_hashes: HashMap::new(),
+            blank_lines_before: HashMap::new(),
+            trailing_trivia: HashMap::new(),
         }
     }
-}
 
-impl Data {
+    /// Like `default`, but with room for `source_count` sources and as many nodes as
+    /// `source_bytes` of source is likely to parse into, so neither arena grows while parsing
+    /// typical data
+    pub fn with_estimated_size(source_bytes: usize, source_count: usize) -> Self {
+        Self::with_arenas(
+            Arena::with_capacity(source_bytes / BYTES_PER_NODE + 1),
+            Arena::with_capacity(source_count),
+        )
+    }
+
+    /// How many nodes the node arena can hold before it has to grow
+    pub fn node_capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    /// How many sources the source arena can hold before it has to grow
+    pub fn source_capacity(&self) -> usize {
+        self.sources.capacity()
+    }
+
+    /// Passed through to `Arena::set_allocation_policy` for the node arena
+    pub fn set_node_allocation_policy(&mut self, allocation_policy: AllocationPolicy) {
+        self.nodes.set_allocation_policy(allocation_policy);
+    }
+
     pub fn error_node(&self) -> NodeIndex {
         self.error_node
     }
@@ -57,10 +129,124 @@ impl Data {
         self.root_nodes.push((source_index, node_index));
     }
 
+    /// Like `push_root_node`, but inserts the root at `position` among the other root nodes, or
+    /// at the end if `position` is past it
+    pub fn insert_root_at(
+        &mut self,
+        position: usize,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+    ) {
+        let position = position.min(self.root_nodes.len());
+
+        self.root_nodes.insert(position, (source_index, node_index));
+    }
+
     pub fn root_nodes(&self) -> &[(SourceIndex, NodeIndex)] {
         self.root_nodes.as_slice()
     }
 
+    /// Moves a root node to `to_source`, keeping its place among the other root nodes
+    ///
+    /// Only the root's entry is changed, so its tokens still have spans into the source it came
+    /// from. Its text should be pushed onto `to_source` and its tokens retargeted to match, or
+    /// its lexemes will be looked up at the same offsets in `to_source`.
+    pub fn move_root(&mut self, root: (SourceIndex, NodeIndex), to_source: SourceIndex) {
+        if let Some(entry) = self.root_nodes.iter_mut().find(|entry| **entry == root) {
+            entry.0 = to_source;
+        }
+    }
+
+    /// Moves every token span of a source's nodes by `delta` where it's at or after `from_offset`,
+    /// such as after text was inserted into or removed from the source
+    ///
+    /// Only the spans are changed, so the source text has to be changed to match separately.
+    /// Offsets that would move before the start of the source are clamped to it.
+    pub fn shift_source_spans(
+        &mut self,
+        source_index: SourceIndex,
+        from_offset: usize,
+        delta: isize,
+    ) {
+        let shift = |offset: usize| {
+            if offset >= from_offset {
+                offset.saturating_add_signed(delta)
+            } else {
+                offset
+            }
+        };
+
+        let subtrees = self
+            .root_nodes
+            .iter()
+            .filter(|(root_source, _root)| *root_source == source_index)
+            .flat_map(|&(_source_index, root)| {
+                self.walk(root).map(|(node_index, _depth)| node_index)
+            })
+            .collect::<HashSet<_>>();
+
+        for node_index in subtrees {
+            for token in self.get_mut_tokens(node_index).unwrap_or_default() {
+                *token = token.map_offsets(shift);
+            }
+        }
+    }
+
+    /// Replaces the text of a source at `span` with `new_text`, and shifts the spans after it to
+    /// match
+    ///
+    /// Tokens ending at the end of `span`, like the one it was taken from, are stretched to cover
+    /// `new_text`. Nothing is changed if `span` isn't on char boundaries within the source.
+    pub fn replace_span_text(&mut self, source_index: SourceIndex, span: Span, new_text: &str) {
+        let (start, end) = (span.start_as_usize(), span.end_as_usize());
+
+        let Some(source) = self.sources.get_mut(source_index.into()) else {
+            return;
+        };
+
+        if start > end || source.get(start..end).is_none() {
+            return;
+        }
+
+        source.replace_range(start..end, new_text);
+
+        let delta = new_text.len() as isize - (end - start) as isize;
+
+        self.shift_source_spans(source_index, end, delta);
+
+        if let Some(trivia) = self.trailing_trivia.get_mut(&source_index)
+            && trivia.start_as_usize() >= end
+        {
+            *trivia = Span::new(
+                trivia.start_as_usize().saturating_add_signed(delta),
+                trivia.end_as_usize().saturating_add_signed(delta),
+            );
+        }
+    }
+
+    /// How many blank lines came before a root node, if it was parsed with
+    /// `Parser::record_blank_lines`
+    pub fn blank_lines_before(&self, root: NodeIndex) -> usize {
+        self.blank_lines_before
+            .get(&root)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_blank_lines_before(&mut self, root: NodeIndex, blank_lines: usize) {
+        self.blank_lines_before.insert(root, blank_lines);
+    }
+
+    /// Everything after the last symbol of a source, if it was parsed with
+    /// `Parser::capture_trivia`
+    pub fn trailing_trivia(&self, source_index: SourceIndex) -> Option<Span> {
+        self.trailing_trivia.get(&source_index).copied()
+    }
+
+    pub(crate) fn set_trailing_trivia(&mut self, source_index: SourceIndex, trivia: Span) {
+        self.trailing_trivia.insert(source_index, trivia);
+    }
+
     pub fn insert_node(&mut self, node: Node) -> NodeIndex {
         self.nodes.insert(node).into()
     }
@@ -73,6 +259,34 @@ impl Data {
         self.nodes.get_mut(index.into())
     }
 
+    /// Frees a single node, leaving any children or root entries that refer to it dangling
+    ///
+    /// The error node is shared and can't be removed
+    pub fn remove_node(&mut self, index: NodeIndex) -> Option<Node> {
+        if index == self.error_node {
+            None
+        } else {
+            self.blank_lines_before.remove(&index);
+            self.nodes.remove(index.into())
+        }
+    }
+
+    pub fn is_valid(&self, index: NodeIndex) -> bool {
+        self.nodes.contains(index.into())
+    }
+
+    pub fn source_is_valid(&self, index: SourceIndex) -> bool {
+        self.sources.contains(index.into())
+    }
+
+    pub fn node_status(&self, index: NodeIndex) -> LookupStatus {
+        self.nodes.lookup_status(index.into())
+    }
+
+    pub fn source_status(&self, index: SourceIndex) -> LookupStatus {
+        self.sources.lookup_status(index.into())
+    }
+
     pub fn push_child(&mut self, node_index: NodeIndex, child_index: NodeIndex) {
         match self.get_mut_node(node_index) {
             None => {}
@@ -122,6 +336,115 @@ impl Data {
         }
     }
 
+    pub fn walk(&self, node_index: NodeIndex) -> Walk<'_> {
+        Walk::new(self, node_index)
+    }
+
+    /// Every token in a subtree with the node it belongs to, in the order they would be written
+    ///
+    /// Nodes are only looked up as the iterator reaches them, and like `walk`, each is visited once
+    pub fn subtree_tokens_iter(
+        &self,
+        node_index: NodeIndex,
+    ) -> impl Iterator<Item = (NodeIndex, Token)> + '_ {
+        self.walk(node_index).flat_map(|(node_index, _depth)| {
+            self.get_tokens(node_index)
+                .unwrap_or_default()
+                .iter()
+                .map(move |&token| (node_index, token))
+        })
+    }
+
+    /// Whether `descendant` can be reached by following children down from `ancestor`
+    ///
+    /// A node isn't its own ancestor, even if a cycle of children leads back to it
+    pub fn is_ancestor_of(&self, ancestor: NodeIndex, descendant: NodeIndex) -> bool {
+        self.walk(ancestor)
+            .skip(1)
+            .any(|(node_index, _depth)| node_index == descendant)
+    }
+
+    /// Like `walk`, but each node comes with the leading keys of every node from `root` down to
+    /// and including it
+    ///
+    /// Nodes without any tokens contribute an empty key
+    pub fn walk_with_path(
+        &self,
+        source_index: SourceIndex,
+        root: NodeIndex,
+    ) -> impl Iterator<Item = (Vec<&str>, NodeIndex)> {
+        let mut keys = vec![];
+
+        self.walk(root).map(move |(node_index, depth)| {
+            let key = self.key_of(source_index, node_index).unwrap_or("");
+
+            keys.truncate(depth);
+            keys.push(key);
+
+            (keys.clone(), node_index)
+        })
+    }
+
+    /// Whether two subtrees have the same lexemes and the same children in the same order, even if
+    /// they come from different sources
+    ///
+    /// A pair of nodes that was already compared is assumed equal, so cycles can't cause an
+    /// infinite comparison. `Error` nodes are equal to each other and to nothing else.
+    pub fn subtree_eq(&self, a: (SourceIndex, NodeIndex), b: (SourceIndex, NodeIndex)) -> bool {
+        let ((a_source, a_root), (b_source, b_root)) = (a, b);
+
+        let mut stack = vec![(a_root, b_root)];
+        let mut visited = HashSet::new();
+
+        while let Some((a_node, b_node)) = stack.pop() {
+            if !visited.insert((a_node, b_node)) {
+                continue;
+            }
+
+            if let (Some(Node::Error), Some(Node::Error)) =
+                (self.get_node(a_node), self.get_node(b_node))
+            {
+                continue;
+            }
+
+            let (Some(a_tokens), Some(b_tokens)) =
+                (self.get_tokens(a_node), self.get_tokens(b_node))
+            else {
+                return false;
+            };
+
+            let same_lexemes = a_tokens.len() == b_tokens.len()
+                && a_tokens.iter().zip(b_tokens).all(|(&a_token, &b_token)| {
+                    self.get_lexeme(a_source, a_token) == self.get_lexeme(b_source, b_token)
+                });
+
+            let a_children = self.get_children(a_node).unwrap_or_default();
+            let b_children = self.get_children(b_node).unwrap_or_default();
+
+            if !same_lexemes || a_children.len() != b_children.len() {
+                return false;
+            }
+
+            stack.extend(a_children.iter().copied().zip(b_children.iter().copied()));
+        }
+
+        true
+    }
+
+    pub fn child_count(&self, node_index: NodeIndex) -> usize {
+        self.get_children(node_index)
+            .map_or(0, |children| children.len())
+    }
+
+    /// The child at `position` among a node's children, if it has that many
+    pub fn child_at(&self, node_index: NodeIndex, position: usize) -> Option<NodeIndex> {
+        self.get_children(node_index)?.get(position).copied()
+    }
+
+    pub fn is_leaf(&self, node_index: NodeIndex) -> bool {
+        self.child_count(node_index) == 0
+    }
+
     pub fn push_token(&mut self, node_index: NodeIndex, token: Token) {
         match self.get_mut_node(node_index) {
             None => {}
@@ -138,6 +461,25 @@ impl Data {
         }
     }
 
+    /// The lexeme of the first token of `node_index`, which is `None` for an empty or error node
+    pub fn key_of(&self, source_index: SourceIndex, node_index: NodeIndex) -> Option<&str> {
+        self.get_tokens(node_index)
+            .and_then(|tokens| tokens.first())
+            .and_then(|&token| self.get_lexeme(source_index, token))
+    }
+
+    /// The position in `keys` of the first one that is the leading lexeme of `node_index`
+    pub fn key_is_one_of(
+        &self,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        keys: &[&str],
+    ) -> Option<usize> {
+        let key = self.key_of(source_index, node_index)?;
+
+        keys.iter().position(|&candidate| candidate == key)
+    }
+
     pub fn get_mut_tokens(&mut self, node_index: NodeIndex) -> Option<&mut [Token]> {
         match self.get_mut_node(node_index) {
             None => None,
@@ -146,10 +488,138 @@ impl Data {
         }
     }
 
+    /// The span from the start of a node's first token to the end of its last token
+    pub fn node_span(&self, node_index: NodeIndex) -> Option<Span> {
+        Span::merge_all(
+            self.get_tokens(node_index)?
+                .iter()
+                .map(|token| token.span()),
+        )
+    }
+
+    /// The 1-based line of the node's first token, counted the same way as in diagnostics
+    pub fn node_line(&self, source_index: SourceIndex, node_index: NodeIndex) -> Option<usize> {
+        let token = self.get_tokens(node_index)?.first()?;
+
+        Some(reporting::line_number(
+            self.get_source(source_index)?,
+            token.span().start_as_usize(),
+        ))
+    }
+
+    /// Finds the node in `source_index` whose own span contains `offset`
+    ///
+    /// A node's span only covers its own line, so this is also the deepest node there
+    pub fn node_at_offset(&self, source_index: SourceIndex, offset: usize) -> Option<NodeIndex> {
+        self.root_nodes()
+            .iter()
+            .filter(|(root_source, _root)| *root_source == source_index)
+            .flat_map(|&(_source_index, root)| self.walk(root))
+            .map(|(node_index, _depth)| node_index)
+            .find(|&node_index| {
+                matches!(
+                    self.node_span(node_index),
+                    Some(span) if span.start_as_usize() <= offset && offset < span.end_as_usize()
+                )
+            })
+    }
+
+    /// Indexes the span of every node in `source_index`, so `OffsetIndex::node_at` can find the
+    /// node at an offset without a walk
+    pub fn build_offset_index(&self, source_index: SourceIndex) -> OffsetIndex {
+        OffsetIndex::new(self, source_index)
+    }
+
+    /// Finds the token containing `offset`, along with the node it belongs to
+    ///
+    /// Offsets in the whitespace between two tokens of a node belong to neither, and return `None`
+    pub fn token_at_offset(
+        &self,
+        source_index: SourceIndex,
+        offset: usize,
+    ) -> Option<(NodeIndex, Token)> {
+        let node_index = self.node_at_offset(source_index, offset)?;
+
+        self.get_tokens(node_index)?
+            .iter()
+            .find(|token| {
+                token.span().start_as_usize() <= offset && offset < token.span().end_as_usize()
+            })
+            .map(|&token| (node_index, token))
+    }
+
     pub fn insert_source(&mut self, source: String) -> SourceIndex {
         self.sources.insert(source).into()
     }
 
+    /// Like `insert_source`, but returns the existing `SourceIndex` if an identical source was
+    /// already inserted through this method
+    ///
+    /// Sources are shared rather than copied, so anything pushed onto a deduplicated source with
+    /// `push_source` is visible through every caller holding that index
+    pub fn insert_source_dedup(&mut self, source: String) -> SourceIndex {
+        let hash = source_hash(&source);
+
+        // sources whose hashes collide share an entry, so each is checked against the text
+        if let Some(&existing) = self.source_hashes.get(&hash).and_then(|indices| {
+            indices
+                .iter()
+                .find(|&&index| self.get_source(index) == Some(source.as_str()))
+        }) {
+            return existing;
+        }
+
+        let source_index = self.insert_source(source);
+
+        self.source_hashes
+            .entry(hash)
+            .or_default()
+            .push(source_index);
+
+        source_index
+    }
+
+    /// Inserts every source with `insert_source_dedup`, returning their indices in order, so
+    /// sources that share identical text, such as boilerplate headers, are only stored once
+    pub fn merge_sources_dedup<I>(&mut self, sources: I) -> Vec<SourceIndex>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        sources
+            .into_iter()
+            .map(|source| self.insert_source_dedup(source))
+            .collect()
+    }
+
+    /// Inserts a source and parses it with a default `Parser`, pushing its root nodes and
+    /// returning its index along with any errors
+    pub fn parse_source(&mut self, source: String) -> (SourceIndex, Vec<ParseError>) {
+        let source_index = self.insert_source(source);
+
+        let mut parser = Parser::new(source_index);
+
+        parser.parse(self);
+
+        (source_index, parser.take_errors())
+    }
+
+    pub fn source_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn source_len(&self, index: SourceIndex) -> Option<usize> {
+        self.get_source(index).map(str::len)
+    }
+
+    /// The length in bytes of every source added together
+    pub fn total_source_bytes(&self) -> usize {
+        self.sources
+            .occupied_by_usize()
+            .filter_map(|index| self.sources.get_by_usize(index))
+            .map(String::len)
+            .sum()
+    }
+
     pub fn get_source(&self, index: SourceIndex) -> Option<&str> {
         self.sources.get(index.into()).map(|s| s.as_str())
     }
@@ -170,10 +640,54 @@ impl Data {
         }
     }
 
+    /// Each line a span covers with its 1-based line number, without its newline
+    ///
+    /// An empty span covers the line it's on, and a span past the end of the source is clamped to
+    /// it. There are no lines if the source doesn't exist.
+    pub fn span_lines(
+        &self,
+        source_index: SourceIndex,
+        span: Span,
+    ) -> impl Iterator<Item = (usize, &str)> {
+        let source = self.get_source(source_index);
+        let span = span.clamp_to(source.map_or(0, str::len));
+
+        let first = span.start_as_usize();
+        let last = span.end_as_usize().saturating_sub(1).max(first);
+
+        let mut line_start = 0;
+
+        source
+            .into_iter()
+            .flat_map(|source| source.split('\n'))
+            .enumerate()
+            .map(move |(i, line)| {
+                let start = line_start;
+                line_start += line.len() + 1;
+
+                (i + 1, start, line)
+            })
+            .skip_while(move |&(_line_number, start, line)| start + line.len() < first)
+            .take_while(move |&(_line_number, start, _line)| start <= last)
+            .map(|(line_number, _start, line)| (line_number, line))
+    }
+
     pub fn get_lexeme(&self, source_index: SourceIndex, token: Token) -> Option<&str> {
         self.get_source(source_index).and_then(|s| token.lexeme(s))
     }
 
+    /// Like `get_lexeme`, but clamps the token's span to the source, so a source that shrank after
+    /// the token was made still gives whatever part of the lexeme is left
+    pub fn get_lexeme_clamped(&self, source_index: SourceIndex, token: Token) -> Option<&str> {
+        self.get_source(source_index)
+            .and_then(|s| token.lexeme_clamped(s))
+    }
+
+    /// Whether `token` resolves to exactly `text`, which is false if it can't be resolved at all
+    pub fn token_eq(&self, source_index: SourceIndex, token: Token, text: &str) -> bool {
+        self.get_lexeme(source_index, token) == Some(text)
+    }
+
     pub fn try_get_number(
         &self,
         source_index: SourceIndex,
@@ -214,109 +728,1955 @@ impl Data {
             })
             .copied()
     }
-}
 
-impl Data {
-    pub fn write(
+    pub fn filter_ci(&self, key: &str) -> impl Iterator<Item = (SourceIndex, NodeIndex)> {
+        self.filter(move |source_index, tokens| {
+            self.leading_lexeme_matches(source_index, tokens, key, str::eq_ignore_ascii_case)
+        })
+    }
+
+    pub fn filter_children_ci(
         &self,
-        output: &mut String,
         source_index: SourceIndex,
         node_index: NodeIndex,
-        indentation: usize,
-    ) -> fmt::Result {
-        let mut infinity_prevention = HashSet::new();
-
-        self.write_recursive(
-            output,
-            source_index,
-            node_index,
-            indentation,
-            &mut infinity_prevention,
-        )
+        key: &str,
+    ) -> impl Iterator<Item = NodeIndex> {
+        self.filter_children(source_index, node_index, move |source_index, tokens| {
+            self.leading_lexeme_matches(source_index, tokens, key, str::eq_ignore_ascii_case)
+        })
     }
 
-    pub fn write_root_nodes(
+    /// Finds the first child of `node_index` whose leading lexeme is `key`
+    pub fn find_first_child(
         &self,
-        output: &mut String,
-        root_nodes: &[(SourceIndex, NodeIndex)],
-    ) -> fmt::Result {
-        let mut infinity_prevention = HashSet::new();
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        key: &str,
+    ) -> Option<NodeIndex> {
+        self.filter_children(source_index, node_index, |source_index, tokens| {
+            self.leading_lexeme_matches(source_index, tokens, key, str::eq)
+        })
+        .next()
+    }
 
-        for (source_index, node_index) in root_nodes {
-            self.write_recursive(
-                output,
-                *source_index,
-                *node_index,
-                0,
-                &mut infinity_prevention,
-            )?;
+    /// Finds the first root node whose leading lexeme is `key`
+    pub fn find_first_root(&self, key: &str) -> Option<(SourceIndex, NodeIndex)> {
+        self.filter(|source_index, tokens| {
+            self.leading_lexeme_matches(source_index, tokens, key, str::eq)
+        })
+        .next()
+    }
 
-            write!(output, "\n\n\n\n")?;
-        }
+    /// Finds every node at the end of `path`, where each element is matched against the leading
+    /// lexeme of the nodes at that depth, starting from the root nodes
+    pub fn find_by_path(&self, path: &[&str]) -> Vec<(SourceIndex, NodeIndex)> {
+        self.find_by_path_matching(path, str::eq)
+    }
 
-        Ok(())
+    /// The same as `find_by_path`, but each key is compared ignoring ASCII case
+    pub fn find_by_path_ci(&self, path: &[&str]) -> Vec<(SourceIndex, NodeIndex)> {
+        self.find_by_path_matching(path, str::eq_ignore_ascii_case)
     }
 
-    fn write_recursive(
+    /// Finds every node in the subtree of `root`, including `root` itself, whose leading lexeme
+    /// is `key`, in the order `walk` visits them
+    pub fn find_all(
+        &self,
+        source_index: SourceIndex,
+        root: NodeIndex,
+        key: &str,
+    ) -> Vec<NodeIndex> {
+        self.walk(root)
+            .map(|(node_index, _depth)| node_index)
+            .filter(|&node_index| self.key_of(source_index, node_index) == Some(key))
+            .collect()
+    }
+
+    pub fn classify<Id: Clone>(
         &self,
-        output: &mut String,
         source_index: SourceIndex,
         node_index: NodeIndex,
-        indentation: usize,
-        infinity_prevention: &mut HashSet<(SourceIndex, NodeIndex)>,
-    ) -> fmt::Result {
-        // if the pair is already in the `HashSet`, it would lead to infinite recursion
-        // it's okay to silently error here because the node was already written
-        if infinity_prevention.contains(&(source_index, node_index)) {
-            return Ok(());
-        }
+        registry: &KeyRegistry<Id>,
+    ) -> Option<Id> {
+        registry
+            .get(self.key_of(source_index, node_index)?)
+            .cloned()
+    }
 
-        infinity_prevention.insert((source_index, node_index));
+    /// Maps the key of each child of `node_index` to the rest of its lexemes
+    ///
+    /// If a key is repeated, the last child using it wins. Children without tokens are skipped.
+    pub fn child_map(
+        &self,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+    ) -> HashMap<String, Vec<String>> {
+        let mut map = HashMap::new();
 
-        // TODO: don't silently error
-        if let Some(Node::Error) = self.get_node(node_index) {
-            return Ok(());
+        for &child in self.get_children(node_index).unwrap_or_default() {
+            let Some((key, values)) = self
+                .get_tokens(child)
+                .and_then(|tokens| tokens.split_first())
+            else {
+                continue;
+            };
+
+            let Some(key) = self.get_lexeme(source_index, *key) else {
+                continue;
+            };
+
+            let values = values
+                .iter()
+                .filter_map(|&token| self.get_lexeme(source_index, token))
+                .map(str::to_owned)
+                .collect();
+
+            map.insert(key.to_owned(), values);
         }
 
-        if let Some(tokens) = self.get_tokens(node_index) {
-            for (i, token) in tokens.iter().enumerate() {
-                if let Some(source) = self.get_source(source_index)
-                    && let Some(lexeme) = token.lexeme(source)
-                    && !lexeme.is_empty()
-                {
-                    if !lexeme.contains(' ') {
-                        write!(output, "{lexeme}")?;
-                    } else if !lexeme.contains('"') {
-                        write!(output, "\"{lexeme}\"")?;
-                    } else {
-                        write!(output, "`{lexeme}`")?;
-                    }
+        map
+    }
 
-                    if i < tokens.len() - 1 {
-                        write!(output, " ")?;
-                    }
+    /// Flattens each root in `source_index` whose key is `root_key` into one record, such as a row
+    /// for CSV export
+    ///
+    /// The root's own values are stored under `root_key`, and each immediate child's values are
+    /// stored under its key, joined by spaces. Anything nested deeper is skipped, and repeated keys
+    /// follow the same last-wins rule as `child_map`.
+    pub fn flat_records(
+        &self,
+        source_index: SourceIndex,
+        root_key: &str,
+    ) -> Vec<HashMap<String, String>> {
+        self.filter(|root_source, tokens| {
+            root_source == source_index
+                && self.leading_lexeme_matches(root_source, tokens, root_key, str::eq)
+        })
+        .map(|(_source_index, root)| {
+            let mut record = self
+                .child_map(source_index, root)
+                .into_iter()
+                .map(|(key, values)| (key, values.join(" ")))
+                .collect::<HashMap<_, _>>();
+
+            let root_values = self
+                .get_tokens(root)
+                .unwrap_or_default()
+                .iter()
+                .skip(1)
+                .filter_map(|&token| self.get_lexeme(source_index, token))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            record.insert(root_key.to_owned(), root_values);
+
+            record
+        })
+        .collect()
+    }
+
+    /// Counts how many nodes, at any depth, use each leading lexeme as their key
+    pub fn key_histogram(&self) -> HashMap<String, usize> {
+        let mut histogram = HashMap::new();
+        let mut seen = HashSet::new();
+
+        for &(source_index, root) in self.root_nodes() {
+            for (node_index, _depth) in self.walk(root) {
+                if seen.insert((source_index, node_index))
+                    && let Some(key) = self.key_of(source_index, node_index)
+                {
+                    *histogram.entry(key.to_owned()).or_insert(0) += 1;
                 }
             }
+        }
 
-            if let Some(children) = self.get_children(node_index)
-                && !children.is_empty()
-            {
-                let indentation = indentation + 1;
-
-                for child in children {
-                    write!(output, "\n{}", "\t".repeat(indentation))?;
-
-                    self.write_recursive(
-                        output,
-                        source_index,
-                        *child,
-                        indentation,
-                        infinity_prevention,
-                    )?;
+        histogram
+    }
+
+    /// Groups every node, at any depth, by its leading lexeme, in the order they were walked
+    pub fn group_by_key(&self) -> HashMap<String, Vec<NodeIndex>> {
+        let mut groups = HashMap::<String, Vec<NodeIndex>>::new();
+        let mut seen = HashSet::new();
+
+        for &(source_index, root) in self.root_nodes() {
+            for (node_index, _depth) in self.walk(root) {
+                if seen.insert((source_index, node_index))
+                    && let Some(key) = self.key_of(source_index, node_index)
+                {
+                    groups.entry(key.to_owned()).or_default().push(node_index);
                 }
             }
         }
 
-        Ok(())
+        groups
+    }
+
+    /// Renames every node whose leading lexeme is `from`, at any depth, returning how many were
+    /// renamed
+    ///
+    /// `to` is pushed onto each affected source once, and every renamed token points at it
+    pub fn rename_key(&mut self, from: &str, to: &str) -> usize {
+        let mut to_rename = vec![];
+        let mut seen = HashSet::new();
+
+        for &(source_index, root) in self.root_nodes() {
+            for (node_index, _depth) in self.walk(root) {
+                if seen.insert((source_index, node_index))
+                    && self.key_of(source_index, node_index) == Some(from)
+                {
+                    to_rename.push((source_index, node_index));
+                }
+            }
+        }
+
+        let mut renamed_spans = HashMap::new();
+
+        for &(source_index, node_index) in to_rename.iter() {
+            let span = match renamed_spans.get(&source_index) {
+                Some(&span) => span,
+                None => {
+                    let Some((start, end)) = self.push_source(source_index, to) else {
+                        continue;
+                    };
+
+                    let span = Span::new(start, end);
+                    renamed_spans.insert(source_index, span);
+                    span
+                }
+            };
+
+            if let Some(token) = self
+                .get_mut_tokens(node_index)
+                .and_then(|tokens| tokens.first_mut())
+            {
+                // keep the trivia so lossless writes still place the new key where the old one was
+                *token = match token.leading_trivia() {
+                    Some(trivia) => Token::new(token.kind(), span).with_leading_trivia(trivia),
+                    None => Token::new(token.kind(), span),
+                };
+            }
+        }
+
+        to_rename.len()
+    }
+
+    /// Removes every child of `node_index` whose tokens don't match `predicate` and frees its
+    /// subtree, returning how many children were removed
+    ///
+    /// Nodes that a root node can still reach through another parent aren't freed.
+    /// Children without tokens are matched as if their tokens were empty. A `Parent` left without
+    /// children stays one until `prune_empty` demotes it.
+    pub fn retain_children<P>(
+        &mut self,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        mut predicate: P,
+    ) -> usize
+    where
+        P: FnMut(SourceIndex, &[Token]) -> bool,
+    {
+        let removed = self
+            .get_children(node_index)
+            .unwrap_or_default()
+            .iter()
+            .copied()
+            .filter(|&child| !predicate(source_index, self.get_tokens(child).unwrap_or_default()))
+            .collect::<HashSet<_>>();
+
+        if removed.is_empty() {
+            return 0;
+        }
+
+        if let Some(Node::Parent { children, .. }) = self.get_mut_node(node_index) {
+            children.retain(|child| !removed.contains(child));
+        }
+
+        self.free_unreachable(&removed.iter().copied().collect::<Vec<_>>(), &[]);
+
+        removed.len()
+    }
+
+    /// Puts `replacement` everywhere `target` is a child or root node, and frees the subtree of
+    /// `target`, returning whether `target` was found anywhere
+    ///
+    /// Nodes that a root node or `replacement` can still reach aren't freed, such as children
+    /// shared with another parent. Nothing is changed if
+    /// `target` isn't found, is the error node, or is in the subtree of `replacement`, since
+    /// replacing it there would make a cycle.
+    pub fn replace_subtree(&mut self, target: NodeIndex, replacement: NodeIndex) -> bool {
+        if target == self.error_node
+            || target == replacement
+            || !self.is_valid(target)
+            || !self.is_valid(replacement)
+            || self.is_ancestor_of(replacement, target)
+        {
+            return false;
+        }
+
+        let mut found = false;
+
+        for (_source_index, root) in self.root_nodes.iter_mut() {
+            if *root == target {
+                *root = replacement;
+                found = true;
+            }
+        }
+
+        let parents = self
+            .nodes
+            .occupied()
+            .map(NodeIndex::from)
+            .filter(|&node_index| {
+                self.get_children(node_index)
+                    .is_some_and(|children| children.contains(&target))
+            })
+            .collect::<Vec<_>>();
+
+        for parent in parents {
+            for child in self.get_mut_children(parent).unwrap_or_default() {
+                if *child == target {
+                    *child = replacement;
+                    found = true;
+                }
+            }
+        }
+
+        if !found {
+            return false;
+        }
+
+        self.free_unreachable(&[target], &[replacement]);
+
+        true
+    }
+
+    // frees every node under `subtrees` that can't be reached from a root node or from `kept`,
+    // so nodes that are shared with another parent stay valid
+    fn free_unreachable(&mut self, subtrees: &[NodeIndex], kept: &[NodeIndex]) {
+        let reachable = self
+            .root_nodes
+            .iter()
+            .map(|&(_source_index, root)| root)
+            .chain(kept.iter().copied())
+            .flat_map(|root| self.walk(root))
+            .map(|(node_index, _depth)| node_index)
+            .collect::<HashSet<_>>();
+
+        let unreachable = subtrees
+            .iter()
+            .flat_map(|&subtree| self.walk(subtree))
+            .map(|(node_index, _depth)| node_index)
+            .filter(|node_index| !reachable.contains(node_index))
+            .collect::<HashSet<_>>();
+
+        for node_index in unreachable {
+            self.remove_node(node_index);
+        }
+    }
+
+    /// Demotes every `Parent` without children to a `Some`, and removes every leaf without tokens
+    /// along with any references to it, returning how many nodes were demoted or removed
+    ///
+    /// This repeats until nothing changes, since removing a leaf can leave its parent empty.
+    /// Nodes with an empty quoted value still have a token, so they are kept.
+    pub fn prune_empty(&mut self) -> usize {
+        let mut pruned = 0;
+
+        loop {
+            let mut removed = HashSet::new();
+
+            for node_index in self
+                .nodes
+                .occupied()
+                .map(NodeIndex::from)
+                .collect::<Vec<_>>()
+            {
+                let Some(node) = self.get_mut_node(node_index) else {
+                    continue;
+                };
+
+                let demoted = if let Node::Parent { tokens, children } = node
+                    && children.is_empty()
+                {
+                    *node = Node::Some {
+                        tokens: mem::take(tokens),
+                    };
+                    true
+                } else {
+                    false
+                };
+
+                // a node that's demoted and then removed is only counted once, as removed
+                if matches!(node, Node::Some { tokens } if tokens.is_empty()) {
+                    self.remove_node(node_index);
+                    removed.insert(node_index);
+                } else if demoted {
+                    pruned += 1;
+                }
+            }
+
+            if removed.is_empty() {
+                break pruned;
+            }
+
+            pruned += removed.len();
+
+            self.root_nodes
+                .retain(|(_source_index, root)| !removed.contains(root));
+
+            for node_index in self
+                .nodes
+                .occupied()
+                .map(NodeIndex::from)
+                .collect::<Vec<_>>()
+            {
+                if let Some(Node::Parent { children, .. }) = self.get_mut_node(node_index) {
+                    children.retain(|child| !removed.contains(child));
+                }
+            }
+        }
+    }
+
+    /// Removes every root node whose subtree is the same as an earlier root's, as compared by
+    /// `subtree_eq`, and frees its subtree, returning how many roots were removed
+    ///
+    /// Nodes that a kept root can still reach aren't freed, which includes a root that was
+    /// listed more than once.
+    ///
+    /// Roots are first grouped by a hash of their lexemes and shape, so only roots in the same
+    /// group are compared
+    pub fn dedup_roots(&mut self) -> usize {
+        let mut kept: HashMap<u64, Vec<(SourceIndex, NodeIndex)>> = HashMap::new();
+        let mut duplicates = HashSet::new();
+
+        for (position, &root) in self.root_nodes.iter().enumerate() {
+            let candidates = kept.entry(self.subtree_hash(root.0, root.1)).or_default();
+
+            if candidates
+                .iter()
+                .any(|&candidate| self.subtree_eq(candidate, root))
+            {
+                duplicates.insert(position);
+            } else {
+                candidates.push(root);
+            }
+        }
+
+        if duplicates.is_empty() {
+            return 0;
+        }
+
+        let (removed, roots): (Vec<_>, Vec<_>) = mem::take(&mut self.root_nodes)
+            .into_iter()
+            .enumerate()
+            .partition(|(position, _root)| duplicates.contains(position));
+
+        self.root_nodes = roots.into_iter().map(|(_position, root)| root).collect();
+
+        // the same node can be reached from a kept root, such as when a root was listed twice
+        let reachable = self
+            .root_nodes
+            .iter()
+            .flat_map(|&(_source_index, root)| self.walk(root))
+            .map(|(node_index, _depth)| node_index)
+            .collect::<HashSet<_>>();
+
+        let unreachable = removed
+            .iter()
+            .flat_map(|&(_position, (_source_index, root))| self.walk(root))
+            .map(|(node_index, _depth)| node_index)
+            .filter(|node_index| !reachable.contains(node_index))
+            .collect::<HashSet<_>>();
+
+        for node_index in unreachable {
+            self.remove_node(node_index);
+        }
+
+        removed.len()
+    }
+
+    /// A hash of the lexemes and shape of a subtree, equal for any two subtrees that `subtree_eq`
+    /// considers the same
+    ///
+    /// The hash only depends on the subtree's contents, so it's the same across runs and platforms
+    /// and can be used as a cache key
+    pub fn subtree_hash(&self, source_index: SourceIndex, root: NodeIndex) -> u64 {
+        let mut hasher = StableHasher::default();
+
+        // every child position is hashed, even when the same node is in several of them, the
+        // way `subtree_eq` compares them, so only nodes already on the path are skipped
+        let mut stack = vec![Some((root, 0))];
+        let mut path = vec![];
+
+        while let Some(entry) = stack.pop() {
+            let Some((node_index, depth)) = entry else {
+                path.pop();
+                continue;
+            };
+
+            hasher.write_u64(depth as u64);
+
+            if path.contains(&node_index) {
+                hasher.write_u8(2);
+                continue;
+            }
+
+            let Some(tokens) = self.get_tokens(node_index) else {
+                hasher.write_u8(0);
+                continue;
+            };
+
+            hasher.write_u8(1);
+            hasher.write_u64(tokens.len() as u64);
+
+            for &token in tokens {
+                let lexeme = self.get_lexeme(source_index, token).unwrap_or_default();
+
+                hasher.write_u64(lexeme.len() as u64);
+                hasher.write(lexeme.as_bytes());
+            }
+
+            path.push(node_index);
+            stack.push(None);
+
+            let children = self.get_children(node_index).unwrap_or_default();
+
+            stack.extend(children.iter().rev().map(|&child| Some((child, depth + 1))));
+        }
+
+        hasher.finish()
+    }
+
+    fn find_by_path_matching(
+        &self,
+        path: &[&str],
+        matches: fn(&str, &str) -> bool,
+    ) -> Vec<(SourceIndex, NodeIndex)> {
+        let Some((first, rest)) = path.split_first() else {
+            return vec![];
+        };
+
+        let mut found = self
+            .filter(|source_index, tokens| {
+                self.leading_lexeme_matches(source_index, tokens, first, matches)
+            })
+            .collect::<Vec<_>>();
+
+        for key in rest {
+            found = found
+                .into_iter()
+                .flat_map(|(source_index, node_index)| {
+                    self.filter_children(source_index, node_index, |source_index, tokens| {
+                        self.leading_lexeme_matches(source_index, tokens, key, matches)
+                    })
+                    .map(move |child| (source_index, child))
+                })
+                .collect();
+        }
+
+        found
+    }
+
+    fn leading_lexeme_matches(
+        &self,
+        source_index: SourceIndex,
+        tokens: &[Token],
+        key: &str,
+        matches: fn(&str, &str) -> bool,
+    ) -> bool {
+        matches!(
+            tokens.first().and_then(|&token| self.get_lexeme(source_index, token)),
+            Some(lexeme) if matches(lexeme, key)
+        )
+    }
+}
+
+impl Data {
+    /// Writes a subtree with its children indented one level more than `indentation`
+    ///
+    /// The node's own line is written wherever `output` ends without any indentation, so it can
+    /// continue a line the caller already started. Each call guards against cycles on its own.
+    pub fn write(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        indentation: usize,
+    ) -> fmt::Result {
+        self.write_with_options(
+            output,
+            source_index,
+            node_index,
+            indentation,
+            &WriteOptions::default(),
+        )
+    }
+
+    pub fn write_with_options(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        indentation: usize,
+        options: &WriteOptions,
+    ) -> fmt::Result {
+        let mut infinity_prevention = HashSet::new();
+
+        self.write_subtree(
+            output,
+            source_index,
+            node_index,
+            indentation,
+            options,
+            &mut infinity_prevention,
+        )
+    }
+
+    /// Writes a subtree on its own, starting at no indentation and ending with a newline
+    ///
+    /// Nothing is written for an `Error` node or a node that doesn't exist, so the string is empty
+    pub fn subtree_to_string(&self, source_index: SourceIndex, node_index: NodeIndex) -> String {
+        let mut output = String::new();
+
+        // writing to a `String` can't fail
+        let _ = self.write(&mut output, source_index, node_index, 0);
+
+        if !output.is_empty() {
+            output.push('\n');
+        }
+
+        output
+    }
+
+    pub fn write_root_nodes(
+        &self,
+        output: &mut String,
+        root_nodes: &[(SourceIndex, NodeIndex)],
+    ) -> fmt::Result {
+        self.write_root_nodes_with_options(output, root_nodes, &WriteOptions::default())
+    }
+
+    pub fn write_root_nodes_with_options(
+        &self,
+        output: &mut String,
+        root_nodes: &[(SourceIndex, NodeIndex)],
+        options: &WriteOptions,
+    ) -> fmt::Result {
+        let mut infinity_prevention = HashSet::new();
+
+        for (i, (source_index, node_index)) in root_nodes.iter().enumerate() {
+            if options.lossless {
+                if i > 0 && !self.has_leading_trivia(*node_index) {
+                    writeln!(output)?;
+                }
+
+                self.write_subtree(
+                    output,
+                    *source_index,
+                    *node_index,
+                    0,
+                    options,
+                    &mut infinity_prevention,
+                )?;
+
+                let last_of_source = root_nodes
+                    .get(i + 1)
+                    .is_none_or(|(next_source, _next_root)| next_source != source_index);
+
+                if last_of_source
+                    && let Some(trivia) = self.trailing_trivia(*source_index)
+                    && let Some(source) = self.get_source(*source_index)
+                    && let Some(trivia) = source.get(trivia.start_as_usize()..trivia.end_as_usize())
+                {
+                    write!(output, "{trivia}")?;
+                }
+
+                continue;
+            }
+
+            self.write_subtree(
+                output,
+                *source_index,
+                *node_index,
+                0,
+                options,
+                &mut infinity_prevention,
+            )?;
+
+            write!(output, "\n\n\n\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// The leading lexeme of every node in a subtree, each on its own line and indented with a
+    /// tab for every level below `root`
+    ///
+    /// Values are left out, as are `Error` nodes and nodes without any tokens
+    pub fn outline(&self, source_index: SourceIndex, root: NodeIndex) -> String {
+        let mut output = String::new();
+
+        for (node_index, depth) in self.walk(root) {
+            if let Some(key) = self.key_of(source_index, node_index) {
+                let _ = writeln!(output, "{}{key}", "\t".repeat(depth));
+            }
+        }
+
+        output
+    }
+
+    fn write_subtree(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        indentation: usize,
+        options: &WriteOptions,
+        infinity_prevention: &mut HashSet<(SourceIndex, NodeIndex)>,
+    ) -> fmt::Result {
+        // an explicit stack of (children, indentation, next child) keeps deep trees off the call
+        // stack
+        let mut stack = vec![];
+
+        if self.write_tokens(
+            output,
+            source_index,
+            node_index,
+            options,
+            infinity_prevention,
+        )? {
+            stack.push((
+                self.children_to_write(source_index, node_index, options),
+                indentation,
+                0,
+            ));
+        }
+
+        while let Some((children, indentation, next_child)) = stack.last_mut() {
+            let Some(&child) = children.get(*next_child) else {
+                stack.pop();
+                continue;
+            };
+
+            *next_child += 1;
+
+            let indentation = *indentation + 1;
+
+            if !options.lossless || !self.has_leading_trivia(child) {
+                write!(output, "\n{}", "\t".repeat(indentation))?;
+            }
+
+            if self.write_tokens(output, source_index, child, options, infinity_prevention)? {
+                stack.push((
+                    self.children_to_write(source_index, child, options),
+                    indentation,
+                    0,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // the children of a node in the order they should be written
+    fn children_to_write(
+        &self,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        options: &WriteOptions,
+    ) -> Vec<NodeIndex> {
+        let mut children = self.get_children(node_index).unwrap_or_default().to_vec();
+
+        if options.sort_children {
+            children.sort_by_key(|&child| self.key_of(source_index, child));
+        }
+
+        children
+    }
+
+    // returns whether the children of the node should be written after its tokens
+    fn write_tokens(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        node_index: NodeIndex,
+        options: &WriteOptions,
+        infinity_prevention: &mut HashSet<(SourceIndex, NodeIndex)>,
+    ) -> Result<bool, fmt::Error> {
+        // every error shares one node, so it's checked before the nodes that were already written
+        if let Some(Node::Error) = self.get_node(node_index) {
+            if options.annotate_errors {
+                write!(output, "# <parse error here>")?;
+            }
+
+            return Ok(false);
+        }
+
+        // if the pair is already in the `HashSet`, it would lead to infinite recursion
+        // it's okay to silently error here because the node was already written
+        if infinity_prevention.contains(&(source_index, node_index)) {
+            return Ok(false);
+        }
+
+        infinity_prevention.insert((source_index, node_index));
+
+        let Some(tokens) = self.get_tokens(node_index) else {
+            return Ok(false);
+        };
+
+        if options.lossless {
+            self.write_lexemes_lossless(output, source_index, tokens)?;
+        } else {
+            self.write_lexemes(output, source_index, tokens)?;
+        }
+
+        Ok(true)
+    }
+
+    // whether the first token of a node has trivia to write in place of its indentation
+    fn has_leading_trivia(&self, node_index: NodeIndex) -> bool {
+        self.get_tokens(node_index)
+            .and_then(|tokens| tokens.first())
+            .is_some_and(|token| token.leading_trivia().is_some())
+    }
+
+    fn write_lexemes_lossless(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        tokens: &[Token],
+    ) -> fmt::Result {
+        let Some(source) = self.get_source(source_index) else {
+            return Ok(());
+        };
+
+        for (i, token) in tokens.iter().enumerate() {
+            let Some(lexeme) = token.lexeme(source) else {
+                continue;
+            };
+
+            match token
+                .leading_trivia()
+                .and_then(|trivia| source.get(trivia.start_as_usize()..trivia.end_as_usize()))
+            {
+                Some(trivia) => write!(output, "{trivia}{lexeme}")?,
+                None => {
+                    if i > 0 {
+                        write!(output, " ")?;
+                    }
+
+                    Self::write_token(output, token, lexeme)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_lexemes(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        tokens: &[Token],
+    ) -> fmt::Result {
+        for (i, token) in tokens.iter().enumerate() {
+            if let Some(source) = self.get_source(source_index)
+                && let Some(lexeme) = token.lexeme(source)
+                && !lexeme.is_empty()
+            {
+                Self::write_token(output, token, lexeme)?;
+
+                if i < tokens.len() - 1 {
+                    write!(output, " ")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // writes a verbatim token as it is, and quotes any other lexeme only if it needs to be
+    fn write_token(output: &mut String, token: &Token, lexeme: &str) -> fmt::Result {
+        if token.symbol_class() == Some(SymbolClass::Verbatim) {
+            write!(output, "{lexeme}")
+        } else {
+            Self::write_lexeme(output, lexeme)
+        }
+    }
+
+    // quotes a lexeme only if it needs to be
+    fn write_lexeme(output: &mut String, lexeme: &str) -> fmt::Result {
+        match quote_for(lexeme) {
+            Some(quote) => write!(output, "{quote}{lexeme}{quote}"),
+            None => write!(output, "{lexeme}"),
+        }
+    }
+
+    /// Writes the root nodes of `source_index`, copying every line whose node is unchanged from
+    /// `original` verbatim, along with the blank and comment lines around it
+    ///
+    /// `original` should be the source text as it was when parsed. A node counts as unchanged if
+    /// all of its tokens still point into `original` and its line holds exactly those tokens, so
+    /// nodes edited through `push_source` or by changing their tokens are rewritten on their old
+    /// line, and new nodes are written after the line before them.
+    pub fn write_preserving(
+        &self,
+        output: &mut String,
+        source_index: SourceIndex,
+        original: &str,
+    ) -> fmt::Result {
+        let mut infinity_prevention = HashSet::new();
+
+        // the end of the last line copied from `original`
+        let mut cursor = None;
+
+        for &(root_source, root) in self.root_nodes() {
+            if root_source != source_index {
+                continue;
+            }
+
+            let mut stack = vec![(root, 0)];
+
+            while let Some((node_index, indentation)) = stack.pop() {
+                if !infinity_prevention.insert(node_index) {
+                    continue;
+                }
+
+                let Some(tokens) = self.get_tokens(node_index) else {
+                    continue;
+                };
+
+                let line = tokens
+                    .iter()
+                    .find(|&&token| self.is_original_token(source_index, token, original))
+                    .map(|token| {
+                        let line_start =
+                            reporting::line_start(original, token.span().start_as_usize());
+
+                        let line_end = original[line_start..]
+                            .find('\n')
+                            .map(|i| line_start + i)
+                            .unwrap_or(original.len());
+
+                        (line_start, line_end)
+                    })
+                    .filter(|&(line_start, _)| cursor.is_none_or(|cursor| line_start > cursor));
+
+                if let Some((line_start, line_end)) = line {
+                    let gap = match cursor {
+                        None => &original[..line_start],
+                        Some(cursor) => {
+                            output.push('\n');
+                            &original[(cursor + 1)..line_start]
+                        }
+                    };
+
+                    for gap_line in gap.split_inclusive('\n') {
+                        if Self::is_blank_or_comment(gap_line) {
+                            output.push_str(gap_line);
+                        }
+                    }
+
+                    if self.is_untouched_line(source_index, tokens, original, line_start, line_end)
+                    {
+                        output.push_str(&original[line_start..line_end]);
+                    } else {
+                        write!(output, "{}", "\t".repeat(indentation))?;
+                        self.write_lexemes(output, source_index, tokens)?;
+                    }
+
+                    cursor = Some(line_end);
+                } else {
+                    if !output.is_empty() {
+                        output.push('\n');
+                    }
+
+                    write!(output, "{}", "\t".repeat(indentation))?;
+                    self.write_lexemes(output, source_index, tokens)?;
+                }
+
+                if let Some(children) = self.get_children(node_index) {
+                    stack.extend(children.iter().rev().map(|&child| (child, indentation + 1)));
+                }
+            }
+        }
+
+        if let Some(cursor) = cursor {
+            for trailing_line in original[cursor..].split_inclusive('\n') {
+                if Self::is_blank_or_comment(trailing_line) {
+                    output.push_str(trailing_line);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_original_token(&self, source_index: SourceIndex, token: Token, original: &str) -> bool {
+        token.span().end_as_usize() <= original.len()
+            && matches!(
+                (self.get_lexeme(source_index, token), token.lexeme(original)),
+                (Some(current), Some(before)) if current == before
+            )
+    }
+
+    // whether `original[line_start..line_end]` holds exactly these tokens, ignoring quotes,
+    // whitespace, and a trailing comment
+    fn is_untouched_line(
+        &self,
+        source_index: SourceIndex,
+        tokens: &[Token],
+        original: &str,
+        line_start: usize,
+        line_end: usize,
+    ) -> bool {
+        let is_filler = |ch: char| ch.is_ascii_whitespace() || ch == '"' || ch == '`';
+
+        if !tokens
+            .iter()
+            .all(|&token| self.is_original_token(source_index, token, original))
+        {
+            return false;
+        }
+
+        let mut previous_end = line_start;
+
+        for token in tokens {
+            let start = token.span().start_as_usize();
+
+            if start < previous_end
+                || token.span().end_as_usize() > line_end
+                || !original[previous_end..start].chars().all(is_filler)
+            {
+                return false;
+            }
+
+            previous_end = token.span().end_as_usize();
+        }
+
+        let rest = original[previous_end..line_end].trim_start_matches(is_filler);
+
+        rest.is_empty() || rest.starts_with('#')
+    }
+
+    fn is_blank_or_comment(line: &str) -> bool {
+        let line = line.trim();
+
+        line.is_empty() || line.starts_with('#')
+    }
+}
+
+impl Data {
+    /// Finds every `Parent` node without any tokens of its own
+    ///
+    /// These should be reported with the span of their first child, since they have none
+    pub fn orphan_indentation(&self) -> Vec<NodeIndex> {
+        self.nodes
+            .occupied()
+            .map(NodeIndex::from)
+            .filter(|&node_index| {
+                matches!(
+                    self.get_node(node_index),
+                    Some(Node::Parent { tokens, children }) if tokens.is_empty() && !children.is_empty()
+                )
+            })
+            .collect()
+    }
+
+    /// Finds every node with more than `max_children` children
+    ///
+    /// These can be reported with their `node_span`
+    pub fn wide_nodes(&self, max_children: usize) -> Vec<NodeIndex> {
+        self.nodes
+            .occupied()
+            .map(NodeIndex::from)
+            .filter(|&node_index| self.child_count(node_index) > max_children)
+            .collect()
+    }
+
+    /// Finds every node nested more than `max_depth` levels below its root node, with its depth
+    ///
+    /// These can be reported as a `LintKind::DeepNesting` with their `node_span`
+    pub fn deep_nodes(&self, max_depth: usize) -> Vec<(NodeIndex, usize)> {
+        let mut deep = vec![];
+        let mut seen = HashSet::new();
+
+        for &(_source_index, root) in self.root_nodes() {
+            for (node_index, depth) in self.walk(root) {
+                if depth > max_depth && seen.insert(node_index) {
+                    deep.push((node_index, depth));
+                }
+            }
+        }
+
+        deep
+    }
+
+    /// Finds every node whose key is in `required_keys` but that has no values after it
+    ///
+    /// These can be reported as a `LintKind::MissingValue` with their `node_span`
+    pub fn missing_values(&self, required_keys: &HashSet<&str>) -> Vec<NodeIndex> {
+        let mut missing = vec![];
+        let mut seen = HashSet::new();
+
+        for &(source_index, root) in self.root_nodes() {
+            for (node_index, _depth) in self.walk(root) {
+                if seen.insert((source_index, node_index))
+                    && self
+                        .get_tokens(node_index)
+                        .is_some_and(|tokens| tokens.len() == 1)
+                    && self
+                        .key_of(source_index, node_index)
+                        .is_some_and(|key| required_keys.contains(key))
+                {
+                    missing.push(node_index);
+                }
+            }
+        }
+
+        missing
+    }
+
+    /// Finds every unquoted symbol that starts like a number but is not a well formed one, such as
+    /// `1.`, `1e` or `.`, suggesting the number that was most likely intended
+    ///
+    /// This is stricter than `try_get_number`, which accepts a trailing `.`
+    pub fn suspicious_numbers(&self) -> Vec<Lint> {
+        self.lint_unquoted_symbols(|lexeme| {
+            (looks_like_number(lexeme) && !is_well_formed_number(lexeme)).then(|| {
+                LintKind::SuspiciousNumber {
+                    suggestion: intended_number(lexeme),
+                }
+            })
+        })
+    }
+
+    // runs `lint` over the lexeme of every unquoted symbol, visiting each node once
+    fn lint_unquoted_symbols<F>(&self, mut lint: F) -> Vec<Lint>
+    where
+        F: FnMut(&str) -> Option<LintKind>,
+    {
+        let mut lints = vec![];
+        let mut seen = HashSet::new();
+
+        for &(source_index, root) in self.root_nodes() {
+            let Some(source) = self.get_source(source_index) else {
+                continue;
+            };
+
+            for (node_index, _depth) in self.walk(root) {
+                if !seen.insert((source_index, node_index)) {
+                    continue;
+                }
+
+                for &token in self.get_tokens(node_index).unwrap_or_default() {
+                    if token.kind() != TokenKind::Symbol
+                        || matches!(
+                            token.symbol_class(),
+                            Some(SymbolClass::Quoted | SymbolClass::Verbatim)
+                        )
+                    {
+                        continue;
+                    }
+
+                    if let Some(lexeme) = token.lexeme(source)
+                        && let Some(kind) = lint(lexeme)
+                    {
+                        lints.push(Lint::new(kind, token.span()));
+                    }
+                }
+            }
+        }
+
+        lints
+    }
+
+    /// Finds every line in `source_index` that is longer than `max_len` once printed, with tabs
+    /// counting as four characters as they do in reports
+    ///
+    /// The spans don't include the newline, and should be reported as warnings
+    pub fn long_lines(&self, source_index: SourceIndex, max_len: usize) -> Vec<Span> {
+        let Some(source) = self.get_source(source_index) else {
+            return vec![];
+        };
+
+        let mut spans = vec![];
+        let mut line_start = 0;
+
+        for line in source.split_inclusive('\n') {
+            let content = line.strip_suffix('\n').unwrap_or(line);
+
+            if reporting::printed_source_length(content) > max_len {
+                spans.push(Span::new(line_start, line_start + content.len()));
+            }
+
+            line_start += line.len();
+        }
+
+        spans
+    }
+
+    /// Finds every line of a space-indented source whose indentation isn't a multiple of the
+    /// first indented line's, such as a line indented by three spaces where the first used two
+    ///
+    /// Sources indented with tabs have no lints, and lines indented with a mix of tabs and spaces
+    /// are skipped since the lexer already reports them
+    pub fn inconsistent_indentation(&self, source_index: SourceIndex) -> Vec<Lint> {
+        let Some(source) = self.get_source(source_index) else {
+            return vec![];
+        };
+
+        let mut lints = vec![];
+        let mut unit = None;
+        let mut line_start = 0;
+
+        for line in source.split_inclusive('\n') {
+            let indentation = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+            let span = Span::new(line_start, line_start + indentation.len());
+
+            line_start += line.len();
+
+            if indentation.is_empty() || Self::is_blank_or_comment(line) {
+                continue;
+            }
+
+            if indentation.contains('\t') {
+                if unit.is_none() {
+                    return vec![];
+                }
+
+                continue;
+            }
+
+            let unit = *unit.get_or_insert(indentation.len());
+
+            if indentation.len() % unit != 0 {
+                lints.push(Lint::new(LintKind::InconsistentIndentation { unit }, span));
+            }
+        }
+
+        lints
+    }
+}
+
+// the quote a lexeme is written with, if it needs one to be read back as a single symbol
+pub(crate) fn quote_for(lexeme: &str) -> Option<char> {
+    if !lexeme.contains(' ') {
+        None
+    } else if !lexeme.contains('"') {
+        Some('"')
+    } else {
+        Some('`')
+    }
+}
+
+fn source_hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+// FNV-1a, which unlike `DefaultHasher` is guaranteed to give the same hash in every build
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // integers are written little-endian so the hash doesn't depend on the platform
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+}
+
+pub(crate) fn looks_like_number(lexeme: &str) -> bool {
+    let unsigned = lexeme.strip_prefix(['-', '+']).unwrap_or(lexeme);
+
+    matches!(unsigned.chars().next(), Some(ch) if ch.is_ascii_digit() || ch == '.')
+}
+
+fn is_well_formed_number(lexeme: &str) -> bool {
+    !lexeme.ends_with('.') && lexeme.parse::<f64>().is_ok()
+}
+
+// the longest well formed prefix, ignoring trailing dots, or zero if there is none
+fn intended_number(lexeme: &str) -> String {
+    lexeme
+        .char_indices()
+        .map(|(i, ch)| &lexeme[..i + ch.len_utf8()])
+        .rev()
+        .map(|prefix| prefix.trim_end_matches('.'))
+        .find(|prefix| looks_like_number(prefix) && is_well_formed_number(prefix))
+        .unwrap_or("0")
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::builder::DataBuilder;
+    use crate::reporting::Reportable;
+
+    // how many times either arena grows while `sources` are parsed into `data`
+    fn arena_growths(mut data: Data, sources: &[String]) -> usize {
+        let mut capacities = (data.node_capacity(), data.source_capacity());
+        let mut growths = 0;
+
+        for source in sources {
+            data.parse_source(source.clone());
+
+            let parsed = (data.node_capacity(), data.source_capacity());
+            growths +=
+                usize::from(parsed.0 != capacities.0) + usize::from(parsed.1 != capacities.1);
+            capacities = parsed;
+        }
+
+        growths
+    }
+
+    #[test]
+    fn with_estimated_size_preallocates_both_arenas() {
+        let sources: Vec<String> = (0..16)
+            .map(|i| {
+                format!(
+                    "ship \"Heavy Shuttle {i}\"\n\tdescription \"A sturdy ship built for long hauls.\"\n\tattributes\n\t\t\"hull repair rate\" 1.25\n\t\t\"outfit space\" 1000\n"
+                )
+            })
+            .collect();
+        let source_bytes = sources.iter().map(String::len).sum();
+
+        let estimated = Data::with_estimated_size(source_bytes, sources.len());
+
+        assert!(estimated.node_capacity() > source_bytes / BYTES_PER_NODE);
+        assert_eq!(estimated.source_capacity(), sources.len());
+        assert_eq!(arena_growths(estimated, &sources), 0);
+        assert!(arena_growths(Data::default(), &sources) > 0);
+    }
+
+    #[test]
+    fn node_line_counts_lines_before_the_first_token() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("ship A\n\tcost 10\n\n# comment\nship B\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let cost = data.child_at(roots[0].1, 0).unwrap();
+
+        assert_eq!(data.node_line(source_index, roots[0].1), Some(1));
+        assert_eq!(data.node_line(source_index, cost), Some(2));
+        assert_eq!(data.node_line(source_index, roots[1].1), Some(5));
+        assert_eq!(data.node_line(source_index, data.error_node()), None);
+    }
+
+    #[test]
+    fn subtrees_with_error_nodes_are_equal_and_hash_the_same() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("ship A\n\tcost 10\nship A\n\tcost 10\nship A\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let error_node = data.error_node();
+
+        data.push_child(roots[0].1, error_node);
+        data.push_child(roots[1].1, error_node);
+        data.push_child(roots[2].1, error_node);
+
+        assert!(data.subtree_eq(roots[0], roots[0]));
+        assert!(data.subtree_eq(roots[0], roots[1]));
+        assert!(!data.subtree_eq(roots[0], roots[2]));
+        assert_eq!(
+            data.subtree_hash(source_index, roots[0].1),
+            data.subtree_hash(source_index, roots[1].1)
+        );
+        assert_ne!(
+            data.subtree_hash(source_index, roots[0].1),
+            data.subtree_hash(source_index, roots[2].1)
+        );
+    }
+
+    #[test]
+    fn subtree_hash_changes_with_a_value() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data
+            .parse_source("ship A\n\tcost 10\nship A\n\tcost 10\nship A\n\tcost 11\n".to_owned());
+
+        let hashes = data
+            .root_nodes()
+            .iter()
+            .map(|&(_source_index, root)| data.subtree_hash(source_index, root))
+            .collect::<Vec<_>>();
+
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+    }
+
+    #[test]
+    fn dedup_roots_keeps_the_first_of_identical_roots() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source(
+            "ship A\n\tcost 10\nship A\n\tcost 10\nship A\n\tcost 11\nship A\n\tcost 10\n"
+                .to_owned(),
+        );
+
+        let roots = data.root_nodes().to_vec();
+
+        assert_eq!(data.dedup_roots(), 2);
+        assert_eq!(data.root_nodes(), &[roots[0], roots[2]]);
+        assert!(!data.is_valid(roots[1].1));
+        assert!(!data.is_valid(roots[3].1));
+
+        let mut output = String::new();
+        data.write_root_nodes(&mut output, data.root_nodes())
+            .unwrap();
+
+        assert_eq!(output, "ship A\n\tcost 10\n\n\n\nship A\n\tcost 11\n\n\n\n");
+        assert_eq!(data.key_of(source_index, roots[0].1), Some("ship"));
+    }
+
+    #[test]
+    fn dedup_roots_keeps_a_root_listed_twice() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("ship A\n\tcost 10\n".to_owned());
+
+        let root = data.root_nodes()[0];
+        let cost = data.child_at(root.1, 0).unwrap();
+
+        data.push_root_node(source_index, root.1);
+
+        assert_eq!(data.dedup_roots(), 1);
+        assert_eq!(data.root_nodes(), &[root]);
+        assert!(data.is_valid(root.1));
+        assert!(data.is_valid(cost));
+    }
+
+    #[test]
+    fn dedup_roots_keeps_children_shared_with_a_kept_root() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("ship A\n\tcost 10\nship A\n\tcost 10\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let cost = data.child_at(roots[0].1, 0).unwrap();
+
+        data.get_mut_children(roots[1].1).unwrap()[0] = cost;
+
+        assert_eq!(data.dedup_roots(), 1);
+        assert!(data.is_valid(cost));
+        assert_eq!(data.key_of(source_index, cost), Some("cost"));
+    }
+
+    #[test]
+    fn write_handles_a_5000_level_deep_tree() {
+        let mut builder = DataBuilder::default();
+
+        let root = builder.root("a", [0; 0]);
+        let mut parent = root;
+
+        for _ in 1..5000 {
+            parent = builder.child(parent, "a", [0; 0]);
+        }
+
+        let data = builder.finish();
+
+        let mut output = String::new();
+        data.write(&mut output, root.source_index(), root.node_index(), 0)
+            .unwrap();
+
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 5000);
+        assert_eq!(lines[4999], format!("{}a", "\t".repeat(4999)));
+    }
+
+    #[test]
+    fn write_preserving_only_rewrites_the_edited_line() {
+        let original = "# ships\nship \"Heavy Shuttle\"\n\tcost   10     # cheap\n\tmass 20\n\n\t\"outfits\"\n\t\t\"Laser\" 2\noutfit Laser\n\t`damage` 5\n\trange 300\n";
+
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source(original.to_owned());
+
+        let ship = data.root_nodes()[0].1;
+        let mass = data.child_at(ship, 1).unwrap();
+        let (start, end) = data.push_source(source_index, "25").unwrap();
+
+        let value = &mut data.get_mut_tokens(mass).unwrap()[1];
+        *value = value.with_span(Span::new(start, end));
+
+        let mut output = String::new();
+        data.write_preserving(&mut output, source_index, original)
+            .unwrap();
+
+        let changed = original
+            .lines()
+            .zip(output.lines())
+            .filter(|(before, after)| before != after)
+            .collect::<Vec<_>>();
+
+        assert_eq!(original.lines().count(), 10);
+        assert_eq!(output.lines().count(), 10);
+        assert_eq!(changed, [("\tmass 20", "\tmass 25")]);
+    }
+
+    #[test]
+    fn lossless_write_round_trips_byte_for_byte() {
+        let source = "# header comment\r\n\nship  \"Heavy Shuttle\"   # trailing comment \r\n\t`cost`\t10  \r\n\t\t\"a b\" `c \"d\"`\n\n\n  # indented comment\noutfit Laser \t\n\tmass 5\n# last comment   \n\n";
+
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut parser = Parser::new(source_index).capture_trivia(true);
+        parser.parse(&mut data);
+
+        let options = WriteOptions {
+            lossless: true,
+            ..WriteOptions::default()
+        };
+
+        let mut output = String::new();
+        data.write_root_nodes_with_options(&mut output, data.root_nodes(), &options)
+            .unwrap();
+
+        assert_eq!(output, source);
+    }
+
+    #[test]
+    fn suspicious_numbers_flags_malformed_unquoted_numbers() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("a 1. 1e .\nb 1.5 word \"1.\" `1e`\n".to_owned());
+
+        let flagged = data
+            .suspicious_numbers()
+            .iter()
+            .map(|lint| {
+                let span = lint.span();
+                let source = data.get_source(source_index).unwrap();
+
+                (
+                    &source[span.start_as_usize()..span.end_as_usize()],
+                    lint.kind().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let suggestion = |suggestion: &str| LintKind::SuspiciousNumber {
+            suggestion: suggestion.to_owned(),
+        };
+
+        assert_eq!(
+            flagged,
+            [
+                ("1.", suggestion("1")),
+                ("1e", suggestion("1")),
+                (".", suggestion("0")),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_sources_dedup_stores_identical_sources_once() {
+        let mut data = Data::default();
+
+        let header = "# boilerplate header\n".to_owned();
+        let indices =
+            data.merge_sources_dedup([header.clone(), "ship A\n".to_owned(), header.clone()]);
+
+        assert_eq!(indices[0], indices[2]);
+        assert_ne!(indices[0], indices[1]);
+        assert_eq!(data.source_count(), 2);
+        assert_eq!(data.insert_source_dedup(header), indices[0]);
+        assert_eq!(data.source_count(), 2);
+    }
+
+    #[test]
+    fn insert_source_dedup_keeps_every_source_sharing_a_hash() {
+        let mut data = Data::default();
+
+        let first = data.insert_source_dedup("ship A\n".to_owned());
+
+        // file the first source under the second one's hash, as a collision would
+        let hash = source_hash("ship B\n");
+        data.source_hashes.entry(hash).or_default().push(first);
+
+        let second = data.insert_source_dedup("ship B\n".to_owned());
+
+        assert_ne!(first, second);
+        assert_eq!(data.source_hashes[&hash], [first, second]);
+        assert_eq!(data.insert_source_dedup("ship B\n".to_owned()), second);
+        assert_eq!(data.insert_source_dedup("ship A\n".to_owned()), first);
+        assert_eq!(data.source_count(), 2);
+    }
+
+    #[test]
+    fn prune_empty_counts_each_node_once() {
+        let mut data = Data::default();
+        // the empty quoted value on its own line still has a token, so it's kept
+        let (_source_index, _errors) = data.parse_source("a\n\tb\nc\n\"\"\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let b = data.child_at(roots[0].1, 0).unwrap();
+
+        // a childless parent with no tokens is both demoted and removed
+        let empty_parent = data.insert_node(Node::Parent {
+            tokens: vec![],
+            children: vec![],
+        });
+        data.push_root_node(roots[0].0, empty_parent);
+
+        // a parent left with no tokens once its only child, which has none either, is removed
+        let empty_leaf = data.insert_node(Node::Some { tokens: vec![] });
+        let emptied_parent = data.insert_node(Node::Parent {
+            tokens: vec![],
+            children: vec![empty_leaf],
+        });
+        data.push_child(roots[1].1, emptied_parent);
+
+        if let Some(Node::Parent { children, .. }) = data.get_mut_node(roots[0].1) {
+            children.clear();
+        }
+
+        assert_eq!(data.prune_empty(), 5);
+        assert_eq!(data.root_nodes(), &roots);
+        assert!(matches!(data.get_node(roots[0].1), Some(Node::Some { .. })));
+        assert!(matches!(data.get_node(roots[1].1), Some(Node::Some { .. })));
+        assert!(data.is_valid(b));
+        assert!(!data.is_valid(empty_parent));
+        assert!(!data.is_valid(emptied_parent));
+        assert!(!data.is_valid(empty_leaf));
+    }
+
+    #[test]
+    fn replace_subtree_puts_the_replacement_in_place_and_frees_the_old_nodes() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("a\n\tc\n\t\tx\n\t\ty\nb\nr 1\n\ts\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let (a, b, r) = (roots[0].1, roots[1].1, roots[2].1);
+        let c = data.child_at(a, 0).unwrap();
+        let x = data.child_at(c, 0).unwrap();
+        let y = data.child_at(c, 1).unwrap();
+
+        data.push_child(b, x);
+
+        assert!(data.replace_subtree(c, r));
+
+        let mut output = String::new();
+        data.write(&mut output, source_index, a, 0).unwrap();
+
+        assert_eq!(output, "a\n\tr 1\n\t\ts");
+        assert!(!data.is_valid(c));
+        assert!(!data.is_valid(y));
+        assert!(data.is_valid(x));
+        assert_eq!(data.get_children(b), Some(&[x][..]));
+        assert_eq!(data.key_of(source_index, x), Some("x"));
+    }
+
+    #[test]
+    fn retain_children_removes_unmatched_children_and_frees_them() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("a\n\tkeep 1\n\tdrop 2\n\t\tunder\n\tkeep 3\n".to_owned());
+
+        let a = data.root_nodes()[0].1;
+        let dropped = data.child_at(a, 1).unwrap();
+        let under = data.child_at(dropped, 0).unwrap();
+
+        let source = data.get_source(source_index).unwrap().to_owned();
+
+        let removed = data.retain_children(source_index, a, |_source_index, tokens| {
+            tokens
+                .first()
+                .is_some_and(|token| token.lexeme(&source) == Some("keep"))
+        });
+
+        assert_eq!(removed, 1);
+
+        let mut output = String::new();
+        data.write(&mut output, source_index, a, 0).unwrap();
+
+        assert_eq!(output, "a\n\tkeep 1\n\tkeep 3");
+        assert!(!data.is_valid(dropped));
+        assert!(!data.is_valid(under));
+    }
+
+    #[test]
+    fn retain_children_keeps_children_shared_with_another_parent() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("a\n\tx\nb\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let (a, b) = (roots[0].1, roots[1].1);
+        let x = data.child_at(a, 0).unwrap();
+
+        data.push_child(b, x);
+
+        assert_eq!(data.retain_children(source_index, a, |_, _| false), 1);
+        assert_eq!(data.get_children(a), Some(&[][..]));
+        assert!(data.is_valid(x));
+        assert_eq!(data.key_of(source_index, x), Some("x"));
+    }
+
+    #[test]
+    fn shared_children_hash_like_separate_copies() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("p\n\tx 1\np\n\tx 1\n\tx 1\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let x = data.child_at(roots[0].1, 0).unwrap();
+
+        data.push_child(roots[0].1, x);
+
+        assert!(data.subtree_eq(roots[0], roots[1]));
+        assert_eq!(
+            data.subtree_hash(source_index, roots[0].1),
+            data.subtree_hash(source_index, roots[1].1)
+        );
+        assert_eq!(data.dedup_roots(), 1);
+        assert_eq!(data.root_nodes(), &roots[..1]);
+    }
+
+    #[test]
+    fn subtree_hash_stops_at_cycles() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("p\n\tx\n".to_owned());
+
+        let p = data.root_nodes()[0].1;
+        let x = data.child_at(p, 0).unwrap();
+
+        data.push_child(x, p);
+
+        data.subtree_hash(source_index, p);
+    }
+
+    #[test]
+    fn ci_queries_match_keys_in_any_case() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("Ship A\n\tCost 10\nship B\n\tcost 20\noutfit C\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+
+        assert_eq!(data.filter_ci("ship").collect::<Vec<_>>(), roots[..2]);
+        assert_eq!(data.find_first_root("ship"), Some(roots[1]));
+        assert_eq!(data.find_by_path(&["ship", "cost"]).len(), 1);
+        assert_eq!(data.find_by_path_ci(&["ship", "cost"]).len(), 2);
+        assert_eq!(data.find_by_path(&["Ship", "cost"]), []);
+
+        let cost = data.child_at(roots[0].1, 0).unwrap();
+
+        assert_eq!(
+            data.filter_children_ci(source_index, roots[0].1, "COST")
+                .collect::<Vec<_>>(),
+            [cost]
+        );
+        assert_eq!(
+            data.find_first_child(source_index, roots[0].1, "cost"),
+            None
+        );
+    }
+
+    #[test]
+    fn orphan_indentation_finds_only_parents_without_tokens() {
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("ship A\n\tcost 10\noutfit B\n\tmass 5\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+        let mass = data.child_at(roots[1].1, 0).unwrap();
+
+        assert!(data.orphan_indentation().is_empty());
+
+        let orphan = data.insert_node(Node::Parent {
+            tokens: vec![],
+            children: vec![mass],
+        });
+        data.push_root_node(source_index, orphan);
+
+        assert_eq!(data.orphan_indentation(), [orphan]);
+    }
+
+    #[test]
+    fn classify_maps_registered_keys_to_their_ids() {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Kind {
+            Ship,
+            Outfit,
+        }
+
+        let mut data = Data::default();
+        let (source_index, _errors) =
+            data.parse_source("ship A\noutfit B\nmission C\n\"\"\n".to_owned());
+
+        let registry = [("ship", Kind::Ship), ("outfit", Kind::Outfit)]
+            .into_iter()
+            .collect::<KeyRegistry<_>>();
+
+        let classified = data
+            .root_nodes()
+            .iter()
+            .map(|&(_source_index, root)| data.classify(source_index, root, &registry))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            classified,
+            [Some(Kind::Ship), Some(Kind::Outfit), None, None]
+        );
+        assert_eq!(
+            data.classify(source_index, data.error_node(), &registry),
+            None
+        );
+    }
+
+    #[test]
+    fn node_line_agrees_with_the_line_diagnostics_report() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source(
+            "# header\n\nship \"A\"\n\tcost 10\n\t\t`x`\n\n\n\toutfits\noutfit B\n".to_owned(),
+        );
+
+        let source = data.get_source(source_index).unwrap().to_owned();
+
+        let lines = data
+            .root_nodes()
+            .iter()
+            .flat_map(|&(_source_index, root)| data.walk(root))
+            .map(|(node_index, _depth)| {
+                let line = data.node_line(source_index, node_index).unwrap();
+                let start = data.get_tokens(node_index).unwrap()[0]
+                    .span()
+                    .start_as_usize();
+                let (report_line, _column) = reporting::line_and_column(&source, start);
+
+                assert_eq!(line, report_line);
+
+                line
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, [3, 4, 5, 8, 9]);
+    }
+
+    #[test]
+    fn removed_node_indices_stay_invalid_after_their_slot_is_reused() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("ship A\n\tcost 10\n".to_owned());
+
+        let root = data.root_nodes()[0].1;
+        let cost = data.child_at(root, 0).unwrap();
+
+        assert!(data.is_valid(root));
+        assert!(data.is_valid(cost));
+
+        data.remove_node(cost);
+
+        assert!(!data.is_valid(cost));
+        assert!(data.is_valid(root));
+
+        let reused = data.insert_node(Node::Some { tokens: vec![] });
+
+        assert!(data.is_valid(reused));
+        assert!(!data.is_valid(cost));
+
+        let mut other = Data::default();
+        other.insert_source(String::new());
+        let missing = other.insert_source(String::new());
+
+        assert!(data.source_is_valid(source_index));
+        assert!(!data.source_is_valid(missing));
+    }
+
+    #[test]
+    fn move_root_moves_a_definition_to_another_source() {
+        let mut data = Data::default();
+        let (ships, _errors) = data.parse_source("ship X\n\tcost 10\noutfit Y\n".to_owned());
+        let (outfits, _errors) = data.parse_source("outfit Z\n".to_owned());
+
+        let [ship, outfit, ..] = *data.root_nodes() else {
+            unreachable!()
+        };
+        let text_end = data.node_span(outfit.1).unwrap().start_as_usize();
+        let text = data.get_source(ships).unwrap()[..text_end].to_owned();
+
+        // move the text along with the root, so its tokens point into the new source
+        let (moved_start, _end) = data.push_source(outfits, text).unwrap();
+
+        for (node_index, _depth) in data.walk(ship.1).collect::<Vec<_>>() {
+            for token in data.get_mut_tokens(node_index).unwrap() {
+                *token = token.map_offsets(|offset| offset + moved_start);
+            }
+        }
+
+        data.move_root(ship, outfits);
+
+        assert_eq!(data.root_nodes()[0], (outfits, ship.1));
+
+        let written = |source_index| {
+            let roots = data
+                .root_nodes()
+                .iter()
+                .filter(|(root_source, _root)| *root_source == source_index)
+                .copied()
+                .collect::<Vec<_>>();
+
+            let mut output = String::new();
+            data.write_root_nodes(&mut output, &roots).unwrap();
+
+            output
+        };
+
+        assert_eq!(written(ships), "outfit Y\n\n\n\n");
+        assert_eq!(
+            written(outfits),
+            "ship X\n\tcost 10\n\n\n\noutfit Z\n\n\n\n"
+        );
+    }
+
+    #[test]
+    fn offset_index_agrees_with_node_at_offset_everywhere() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source(
+            "# header\nship \"Heavy Shuttle\"\n\tcost 10\n\n\tattributes\n\t\tmass 5 # heavy\noutfit `Laser`\n"
+                .to_owned(),
+        );
+        let (other_source, _errors) = data.parse_source("ship B\n".to_owned());
+
+        let index = data.build_offset_index(source_index);
+        let source_len = data.source_len(source_index).unwrap();
+
+        assert_eq!(index.len(), 5);
+
+        for offset in 0..=source_len + 2 {
+            assert_eq!(
+                index.node_at(offset),
+                data.node_at_offset(source_index, offset),
+                "at offset {offset}"
+            );
+        }
+
+        let mass = data.get_source(source_index).unwrap().find("mass").unwrap();
+
+        assert_eq!(
+            index
+                .node_at(mass + 2)
+                .and_then(|node_index| data.key_of(source_index, node_index)),
+            Some("mass")
+        );
+        assert!(data.build_offset_index(other_source).node_at(0).is_some());
+        assert!(index.node_at(0).is_none());
     }
 }
@@ -0,0 +1,194 @@
+use crate::data::{self, Data, Node, NodeIndex, SourceIndex};
+use crate::lex::token::{SymbolClass, Token, TokenKind};
+use crate::reporting::Span;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeRef {
+    source_index: SourceIndex,
+    node_index: NodeIndex,
+}
+
+impl NodeRef {
+    pub fn source_index(&self) -> SourceIndex {
+        self.source_index
+    }
+
+    pub fn node_index(&self) -> NodeIndex {
+        self.node_index
+    }
+}
+
+pub struct DataBuilder {
+    data: Data,
+    source_index: SourceIndex,
+}
+
+impl Default for DataBuilder {
+    fn default() -> Self {
+        let mut data = Data::default();
+        let source_index = data.insert_source(String::new());
+
+        Self { data, source_index }
+    }
+}
+
+impl DataBuilder {
+    pub fn source_index(&self) -> SourceIndex {
+        self.source_index
+    }
+
+    pub fn root<K, I>(&mut self, key: K, values: I) -> NodeRef
+    where
+        K: fmt::Display,
+        I: IntoIterator,
+        I::Item: fmt::Display,
+    {
+        let node_index = self.node(key, values);
+
+        self.data.push_root_node(self.source_index, node_index);
+
+        NodeRef {
+            source_index: self.source_index,
+            node_index,
+        }
+    }
+
+    pub fn child<K, I>(&mut self, parent: NodeRef, key: K, values: I) -> NodeRef
+    where
+        K: fmt::Display,
+        I: IntoIterator,
+        I::Item: fmt::Display,
+    {
+        let node_index = self.node(key, values);
+
+        self.data.push_child(parent.node_index, node_index);
+
+        NodeRef {
+            source_index: parent.source_index,
+            node_index,
+        }
+    }
+
+    pub fn finish(self) -> Data {
+        self.data
+    }
+
+    fn node<K, I>(&mut self, key: K, values: I) -> NodeIndex
+    where
+        K: fmt::Display,
+        I: IntoIterator,
+        I::Item: fmt::Display,
+    {
+        let mut tokens = vec![self.token(key)];
+
+        for value in values {
+            self.data.push_source(self.source_index, ' ');
+            tokens.push(self.token(value));
+        }
+
+        // keep each node on its own line so the generated source reads like a data file
+        self.data.push_source(self.source_index, '\n');
+
+        self.data.insert_node(Node::Some { tokens })
+    }
+
+    // quotes the text in the source the way `Data::write` would, so the source parses back into
+    // the same tokens
+    fn token<T: fmt::Display>(&mut self, text: T) -> Token {
+        let text = text.to_string();
+        let quote = data::quote_for(&text);
+
+        if let Some(quote) = quote {
+            self.data.push_source(self.source_index, quote);
+        }
+
+        let Some((start, end)) = self.data.push_source(self.source_index, &text) else {
+            unreachable!("DataBuilder source was removed")
+        };
+
+        let symbol_class = match quote {
+            Some(quote) => {
+                self.data.push_source(self.source_index, quote);
+
+                SymbolClass::Quoted
+            }
+            None => SymbolClass::of_unquoted(&text),
+        };
+
+        Token::new(TokenKind::Symbol, Span::new(start, end)).with_symbol_class(symbol_class)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_level_tree_writes_back_out() {
+        let mut builder = DataBuilder::default();
+
+        let ship = builder.root("ship", ["Shuttle"]);
+        builder.child(ship, "cost", [10]);
+        builder.child(ship, "description", ["A small ship"]);
+        builder.root("outfit", ["Laser", "Turret"]);
+
+        let data = builder.finish();
+
+        let mut output = String::new();
+        data.write_root_nodes(&mut output, data.root_nodes())
+            .unwrap();
+
+        assert_eq!(
+            output,
+            "ship Shuttle\n\tcost 10\n\tdescription \"A small ship\"\n\n\n\noutfit Laser Turret\n\n\n\n"
+        );
+    }
+
+    #[test]
+    fn values_with_spaces_are_quoted_in_the_source_and_read_back() {
+        let mut builder = DataBuilder::default();
+
+        let ship = builder.root("ship", ["Heavy Shuttle"]);
+        builder.child(ship, "description", ["A \"small\" ship"]);
+        builder.child(ship, "hull repair rate", [1.5]);
+
+        let source_index = builder.source_index();
+        let data = builder.finish();
+
+        let mut output = String::new();
+        data.write_root_nodes(&mut output, data.root_nodes())
+            .unwrap();
+
+        let source = data.get_source(source_index).unwrap();
+
+        assert_eq!(
+            source,
+            "ship \"Heavy Shuttle\"\ndescription `A \"small\" ship`\n\"hull repair rate\" 1.5\n"
+        );
+        assert_eq!(
+            output,
+            "ship \"Heavy Shuttle\"\n\tdescription `A \"small\" ship`\n\t\"hull repair rate\" 1.5\n\n\n\n"
+        );
+
+        let mut reparsed = Data::default();
+        let (reparsed_index, errors) = reparsed.parse_source(output);
+
+        assert!(errors.is_empty());
+
+        let lexemes = |data: &Data, source_index| {
+            data.root_nodes()
+                .iter()
+                .flat_map(|&(_source_index, root)| data.walk(root))
+                .flat_map(|(node, _depth)| data.get_tokens(node).unwrap_or_default().to_vec())
+                .map(|token| data.get_lexeme(source_index, token).unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lexemes(&reparsed, reparsed_index),
+            lexemes(&data, source_index)
+        );
+    }
+}
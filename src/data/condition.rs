@@ -0,0 +1,88 @@
+use crate::data::{Data, NodeIndex, SourceIndex};
+use crate::lex::token::SymbolClass;
+
+const COMPARISONS: &[&str] = &[
+    "==", "!=", "<", ">", "<=", ">=", "=", "+=", "-=", "*=", "/=", "<?=", ">?=",
+];
+
+const PREFIX_OPERATORS: &[&str] = &["has", "not", "set", "clear"];
+
+const POSTFIX_OPERATORS: &[&str] = &["++", "--"];
+
+/// One line of a condition block, like `credits >= 1000` or `has "event: foo"`
+///
+/// For a prefix or postfix operator like `has` or `++`, `lhs` is the condition the operator
+/// applies to and there is no `rhs`. Expressions on either side of a comparison are joined by
+/// spaces, so `rhs` of `a >= b + 5` is `"b + 5"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Condition {
+    pub lhs: String,
+    pub op: String,
+    pub rhs: Option<String>,
+}
+
+impl Data {
+    /// Splits each child of a condition block, such as a mission's `to offer` or `on complete`
+    /// node, into a `Condition`
+    ///
+    /// Children that aren't a single condition, such as nested `and` and `or` blocks, are
+    /// skipped. Quoted operators are treated as values.
+    pub fn conditions_under(&self, source_index: SourceIndex, node: NodeIndex) -> Vec<Condition> {
+        self.get_children(node)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|&child| {
+                let tokens = self.get_tokens(child)?;
+
+                let lexemes = tokens
+                    .iter()
+                    .map(|&token| {
+                        let lexeme = self.get_lexeme(source_index, token)?;
+                        let quoted = token.symbol_class() == Some(SymbolClass::Quoted);
+
+                        Some((lexeme, quoted))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                let is_operator = |(lexeme, quoted): (&str, bool), operators: &[&str]| {
+                    !quoted && operators.contains(&lexeme)
+                };
+
+                let join = |lexemes: &[(&str, bool)]| {
+                    lexemes
+                        .iter()
+                        .map(|&(lexeme, _quoted)| lexeme)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+
+                if let Some(i) = lexemes
+                    .iter()
+                    .position(|&lexeme| is_operator(lexeme, COMPARISONS))
+                    && i > 0
+                    && i + 1 < lexemes.len()
+                {
+                    return Some(Condition {
+                        lhs: join(&lexemes[..i]),
+                        op: lexemes[i].0.to_owned(),
+                        rhs: Some(join(&lexemes[i + 1..])),
+                    });
+                }
+
+                match *lexemes.as_slice() {
+                    [op, lhs] if is_operator(op, PREFIX_OPERATORS) => Some(Condition {
+                        lhs: lhs.0.to_owned(),
+                        op: op.0.to_owned(),
+                        rhs: None,
+                    }),
+                    [lhs, op] if is_operator(op, POSTFIX_OPERATORS) => Some(Condition {
+                        lhs: lhs.0.to_owned(),
+                        op: op.0.to_owned(),
+                        rhs: None,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,470 @@
+use crate::data::{Data, Node, NodeIndex, SourceIndex};
+use crate::parse::{self, Parser, error::ParseError};
+use crate::reporting::{self, Span};
+
+use std::{collections::HashSet, ops::Range};
+
+/// A change to the text of a source, replacing the bytes in `range` with `new_text`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub new_text: String,
+}
+
+impl Edit {
+    pub fn new(range: Range<usize>, new_text: impl Into<String>) -> Self {
+        Self {
+            range,
+            new_text: new_text.into(),
+        }
+    }
+}
+
+impl Data {
+    /// Applies `edit` to a source and reparses only the root nodes whose region it touches,
+    /// returning any errors from the reparsed region
+    ///
+    /// A root's region runs from the start of its first line to the start of the next root, so
+    /// comments and blank lines belong to the root above them. Every other root node keeps its
+    /// index, and the spans of those after the edit are shifted to match the new text.
+    ///
+    /// Anything pushed onto the end of the source is treated as part of the last region, so this
+    /// is meant for sources that are only changed through edits
+    pub fn reparse_range(&mut self, source_index: SourceIndex, edit: Edit) -> Vec<ParseError> {
+        let Some(source) = self.get_source(source_index) else {
+            return vec![];
+        };
+
+        let source_len = source.len();
+
+        if edit.range.start > edit.range.end
+            || edit.range.end > source_len
+            || !source.is_char_boundary(edit.range.start)
+            || !source.is_char_boundary(edit.range.end)
+        {
+            return vec![];
+        }
+
+        // regions follow the order roots appear in the source, which `insert_root_at` and
+        // `move_root` can make different from their order in `root_nodes`
+        let mut roots = self
+            .root_nodes()
+            .iter()
+            .filter(|(root_source, _root)| *root_source == source_index)
+            .map(|&(_source_index, root)| (self.subtree_start(root).unwrap_or(0), root))
+            .collect::<Vec<_>>();
+
+        roots.sort_by_key(|&(start, _root)| start);
+
+        // the start of each root's region, where the first always starts at the top of the source
+        let region_starts = roots
+            .iter()
+            .enumerate()
+            .map(|(i, &(start, _root))| match i {
+                0 => 0,
+                _ => reporting::line_start(source, start),
+            })
+            .collect::<Vec<_>>();
+
+        let region_end = |i: usize| region_starts.get(i + 1).copied().unwrap_or(source_len);
+
+        // edits touching the boundary between two regions affect both of them
+        let affected = (0..roots.len())
+            .filter(|&i| region_starts[i] <= edit.range.end && edit.range.start <= region_end(i))
+            .collect::<Vec<_>>();
+
+        let (reparse_start, reparse_end, affected) = match (affected.first(), affected.last()) {
+            (Some(&first), Some(&last)) => {
+                (region_starts[first], region_end(last), first..last + 1)
+            }
+            _ => (0, source_len, 0..roots.len()),
+        };
+
+        let roots = roots
+            .into_iter()
+            .map(|(_start, root)| root)
+            .collect::<Vec<_>>();
+
+        let earlier_roots = &roots[..affected.start];
+        let affected_roots = &roots[affected.clone()];
+        let later_roots = &roots[affected.end..];
+
+        let previous_end = self.roots_end(earlier_roots);
+
+        // the reparsed roots are parsed the way the rest of the source was
+        let record_blank_lines = roots
+            .iter()
+            .any(|root| self.blank_lines_before.contains_key(root));
+        let capture_trivia = self.trailing_trivia.contains_key(&source_index);
+
+        let removed_len = edit.range.end - edit.range.start;
+        let inserted_len = edit.new_text.len();
+        let shift = |offset: usize| offset + inserted_len - removed_len;
+
+        if let Some(source) = self.sources.get_mut(source_index.into()) {
+            source.replace_range(edit.range.clone(), edit.new_text.as_str());
+        }
+
+        for &root in later_roots {
+            let subtree = self
+                .walk(root)
+                .map(|(node_index, _depth)| node_index)
+                .collect::<Vec<_>>();
+
+            for node_index in subtree {
+                for token in self.get_mut_tokens(node_index).unwrap_or_default() {
+                    *token = token.map_offsets(shift);
+                }
+            }
+        }
+
+        if let Some(trivia) = self.trailing_trivia.get_mut(&source_index)
+            && trivia.start_as_usize() >= edit.range.end
+        {
+            *trivia = Span::new(shift(trivia.start_as_usize()), shift(trivia.end_as_usize()));
+        }
+
+        let trailing_trivia = self.trailing_trivia(source_index);
+
+        for &root in affected_roots {
+            let subtree = self
+                .walk(root)
+                .map(|(node_index, _depth)| node_index)
+                .collect::<Vec<_>>();
+
+            for node_index in subtree {
+                self.remove_node(node_index);
+            }
+        }
+
+        let mut parser = Parser::new_at(source_index, reparse_start)
+            .stop_at(shift(reparse_end))
+            .capture_trivia(capture_trivia)
+            .record_blank_lines(record_blank_lines)
+            .continue_after(previous_end);
+
+        let new_roots = parser.parse_roots(self);
+
+        // the parser only knows where the source ends when it parsed up to the end
+        if reparse_end < source_len {
+            match trailing_trivia {
+                Some(trivia) => self.set_trailing_trivia(source_index, trivia),
+                None => {
+                    self.trailing_trivia.remove(&source_index);
+                }
+            }
+        }
+
+        // the blank lines before the next root may have been edited as part of the region
+        if record_blank_lines
+            && let Some(&next_root) = later_roots.first()
+            && let Some(next_start) = self.subtree_start(next_root)
+            && let Some(source) = self.get_source(source_index)
+        {
+            let previous_end = self.roots_end(&new_roots).or(previous_end);
+            let blank_lines = parse::blank_lines_between(source, previous_end, 0, next_start);
+
+            self.set_blank_lines_before(next_root, blank_lines);
+        }
+
+        let insert_at = self
+            .root_nodes
+            .iter()
+            .position(|(root_source, root)| {
+                *root_source == source_index && affected_roots.contains(root)
+            })
+            .unwrap_or(self.root_nodes.len());
+
+        self.root_nodes.retain(|(root_source, root)| {
+            *root_source != source_index || !affected_roots.contains(root)
+        });
+
+        let insert_at = insert_at.min(self.root_nodes.len());
+
+        self.root_nodes.splice(
+            insert_at..insert_at,
+            new_roots.into_iter().map(|root| (source_index, root)),
+        );
+
+        parser.take_errors()
+    }
+
+    /// Replaces the text of every source with `f` applied to it, and reparses the root nodes of
+    /// each source that had any, returning the errors from reparsing
+    ///
+    /// Every node of a reparsed source is freed and replaced, so any `NodeIndex` into one is
+    /// invalid afterwards. The new root nodes take the place of the old ones among the other root
+    /// nodes, and are parsed with a default `Parser`, so blank lines and trivia recorded for the
+    /// old ones are lost.
+    pub fn map_sources(
+        &mut self,
+        mut f: impl FnMut(&str) -> String,
+    ) -> Vec<(SourceIndex, ParseError)> {
+        let source_indices = self
+            .sources
+            .occupied()
+            .map(SourceIndex::from)
+            .collect::<Vec<_>>();
+
+        // only sources inserted through `insert_source_dedup` are looked up by their text
+        let deduplicated = self
+            .source_hashes
+            .drain()
+            .flat_map(|(_hash, source_indices)| source_indices)
+            .collect::<HashSet<_>>();
+
+        for &source_index in source_indices.iter() {
+            let Some(source) = self.sources.get_mut(source_index.into()) else {
+                continue;
+            };
+
+            *source = f(source);
+
+            if deduplicated.contains(&source_index) {
+                let hash = super::source_hash(source);
+
+                self.source_hashes
+                    .entry(hash)
+                    .or_default()
+                    .push(source_index);
+            }
+        }
+
+        let mut errors = vec![];
+
+        for source_index in source_indices {
+            let Some(insert_at) = self
+                .root_nodes
+                .iter()
+                .position(|(root_source, _root)| *root_source == source_index)
+            else {
+                continue;
+            };
+
+            let old_roots = self
+                .root_nodes
+                .iter()
+                .filter(|(root_source, _root)| *root_source == source_index)
+                .map(|&(_source_index, root)| root)
+                .collect::<Vec<_>>();
+
+            for root in old_roots {
+                let subtree = self
+                    .walk(root)
+                    .map(|(node_index, _depth)| node_index)
+                    .collect::<Vec<_>>();
+
+                for node_index in subtree {
+                    self.remove_node(node_index);
+                }
+
+                self.blank_lines_before.remove(&root);
+            }
+
+            self.root_nodes
+                .retain(|(root_source, _root)| *root_source != source_index);
+            self.trailing_trivia.remove(&source_index);
+
+            let mut parser = Parser::new(source_index);
+
+            let new_roots = parser.parse_roots(self);
+
+            self.root_nodes.splice(
+                insert_at..insert_at,
+                new_roots.into_iter().map(|root| (source_index, root)),
+            );
+
+            errors.extend(
+                parser
+                    .take_errors()
+                    .into_iter()
+                    .map(|error| (source_index, error)),
+            );
+        }
+
+        errors
+    }
+
+    // the latest end of any token under `roots`
+    fn roots_end(&self, roots: &[NodeIndex]) -> Option<usize> {
+        roots
+            .iter()
+            .flat_map(|&root| self.walk(root))
+            .filter_map(|(node_index, _depth)| self.get_tokens(node_index)?.last().copied())
+            .map(|token| token.span().end_as_usize())
+            .max()
+    }
+
+    // the earliest offset of any token in a subtree
+    fn subtree_start(&self, root: NodeIndex) -> Option<usize> {
+        self.walk(root)
+            .filter_map(|(node_index, _depth)| match self.get_node(node_index)? {
+                Node::Some { tokens } | Node::Parent { tokens, .. } => {
+                    tokens.first().map(|token| token.span().start_as_usize())
+                }
+                Node::Error => None,
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::WriteOptions;
+
+    // every root of the source written on one line each
+    fn written_roots(data: &Data, source_index: SourceIndex) -> Vec<String> {
+        data.root_nodes()
+            .iter()
+            .filter(|(root_source, _root)| *root_source == source_index)
+            .map(|&(_source_index, root)| {
+                let mut output = String::new();
+                data.write(&mut output, source_index, root, 0).unwrap();
+                output.trim_end().to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reparse_range_keeps_the_indices_of_untouched_roots() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("a 1\nb 2\n\tx\nc 3\nd 4\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+
+        let errors = data.reparse_range(source_index, Edit::new(13..14, "30"));
+
+        assert!(errors.is_empty());
+        assert_eq!(
+            data.get_source(source_index),
+            Some("a 1\nb 2\n\tx\nc 30\nd 4\n")
+        );
+        assert_eq!(
+            written_roots(&data, source_index),
+            ["a 1", "b 2\n\tx", "c 30", "d 4"]
+        );
+        assert_eq!(data.root_nodes()[0], roots[0]);
+        assert_eq!(data.root_nodes()[1], roots[1]);
+        assert_ne!(data.root_nodes()[2], roots[2]);
+        assert_eq!(data.root_nodes()[3], roots[3]);
+    }
+
+    #[test]
+    fn reparse_range_inserts_a_root_at_a_boundary() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("a 1\nb 2\nc 3\nd 4\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+
+        data.reparse_range(source_index, Edit::new(4..4, "x 9\n"));
+
+        assert_eq!(
+            written_roots(&data, source_index),
+            ["a 1", "x 9", "b 2", "c 3", "d 4"]
+        );
+        assert_eq!(data.root_nodes()[3], roots[2]);
+        assert_eq!(data.root_nodes()[4], roots[3]);
+    }
+
+    #[test]
+    fn reparse_range_deletes_a_root() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("a 1\nb 2\nc 3\nd 4\n".to_owned());
+
+        let roots = data.root_nodes().to_vec();
+
+        data.reparse_range(source_index, Edit::new(4..8, ""));
+
+        assert_eq!(written_roots(&data, source_index), ["a 1", "c 3", "d 4"]);
+        assert!(!data.is_valid(roots[1].1));
+        assert_eq!(data.root_nodes()[2], roots[3]);
+    }
+
+    #[test]
+    fn map_sources_substitutes_text_and_reparses() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("ship NAME\n\tcost 10\n".to_owned());
+
+        let errors = data.map_sources(|source| source.replace("NAME", "Shuttle"));
+
+        assert!(errors.is_empty());
+        assert_eq!(
+            written_roots(&data, source_index),
+            ["ship Shuttle\n\tcost 10"]
+        );
+    }
+
+    #[test]
+    fn map_sources_only_rehashes_deduplicated_sources() {
+        let mut data = Data::default();
+
+        let plain = data.insert_source("a\n".to_owned());
+        let deduplicated = data.insert_source_dedup("b\n".to_owned());
+
+        data.map_sources(|source| source.to_uppercase());
+
+        assert_ne!(data.insert_source_dedup("A\n".to_owned()), plain);
+        assert_eq!(data.insert_source_dedup("B\n".to_owned()), deduplicated);
+    }
+
+    #[test]
+    fn reparse_range_keeps_trivia_for_a_lossless_write() {
+        let mut data = Data::default();
+        let source_index = data.insert_source("a\nb  # note\n\n# tail\n".to_owned());
+
+        let mut parser = Parser::new(source_index).capture_trivia(true);
+        parser.parse(&mut data);
+
+        data.reparse_range(source_index, Edit::new(0..1, "aaaa"));
+        data.reparse_range(source_index, Edit::new(5..6, "bb"));
+
+        let options = WriteOptions {
+            lossless: true,
+            ..WriteOptions::default()
+        };
+
+        let mut output = String::new();
+        data.write_root_nodes_with_options(&mut output, data.root_nodes(), &options)
+            .unwrap();
+
+        assert_eq!(output, "aaaa\nbb  # note\n\n# tail\n");
+    }
+
+    #[test]
+    fn reparse_range_recounts_blank_lines_after_the_region() {
+        let mut data = Data::default();
+        let source_index = data.insert_source("a\n\n\nb\nc\n".to_owned());
+
+        let mut parser = Parser::new(source_index).record_blank_lines(true);
+        parser.parse(&mut data);
+
+        let roots = data.root_nodes().to_vec();
+
+        data.reparse_range(source_index, Edit::new(2..3, ""));
+
+        assert_eq!(data.root_nodes()[1], roots[1]);
+        assert_eq!(data.blank_lines_before(roots[1].1), 1);
+
+        data.reparse_range(source_index, Edit::new(0..1, "\na"));
+
+        assert_eq!(data.blank_lines_before(data.root_nodes()[0].1), 1);
+        assert_eq!(data.blank_lines_before(roots[1].1), 1);
+    }
+
+    #[test]
+    fn reparse_range_finds_regions_by_span_when_roots_are_out_of_order() {
+        let mut data = Data::default();
+        let (source_index, _errors) = data.parse_source("a 1\nb 2\nc 3\n".to_owned());
+
+        data.root_nodes.reverse();
+
+        let roots = data.root_nodes().to_vec();
+
+        data.reparse_range(source_index, Edit::new(6..7, "20"));
+
+        assert_eq!(written_roots(&data, source_index), ["c 3", "b 20", "a 1"]);
+        assert_eq!(data.root_nodes()[0], roots[0]);
+        assert_eq!(data.root_nodes()[2], roots[2]);
+    }
+}
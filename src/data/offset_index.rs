@@ -0,0 +1,53 @@
+use crate::data::{Data, NodeIndex, SourceIndex};
+use crate::reporting::Span;
+
+use std::collections::HashSet;
+
+/// The span of every node in a source sorted by where it starts, for answering many
+/// `Data::node_at_offset` queries without walking the tree each time
+///
+/// This is a snapshot, so it has to be rebuilt with `Data::build_offset_index` after the source's
+/// nodes or spans change
+pub struct OffsetIndex {
+    spans: Vec<(Span, NodeIndex)>,
+}
+
+impl OffsetIndex {
+    pub(super) fn new(data: &Data, source_index: SourceIndex) -> Self {
+        let mut seen = HashSet::new();
+
+        let mut spans = data
+            .root_nodes()
+            .iter()
+            .filter(|(root_source, _root)| *root_source == source_index)
+            .flat_map(|&(_source_index, root)| data.walk(root))
+            .filter(|&(node_index, _depth)| seen.insert(node_index))
+            .filter_map(|(node_index, _depth)| Some((data.node_span(node_index)?, node_index)))
+            .filter(|(span, _node_index)| span.start_as_usize() < span.end_as_usize())
+            .collect::<Vec<_>>();
+
+        spans.sort_by_key(|(span, _node_index)| span.start_as_usize());
+
+        Self { spans }
+    }
+
+    /// The same node `Data::node_at_offset` finds, as long as no two node spans overlap, which
+    /// they never do in parsed data
+    pub fn node_at(&self, offset: usize) -> Option<NodeIndex> {
+        let after = self
+            .spans
+            .partition_point(|(span, _node_index)| span.start_as_usize() <= offset);
+
+        let &(span, node_index) = self.spans.get(after.checked_sub(1)?)?;
+
+        (offset < span.end_as_usize()).then_some(node_index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+/// Maps known leading keys to a caller-defined id, for use with `Data::classify`
+pub struct KeyRegistry<Id> {
+    keys: HashMap<String, Id>,
+}
+
+impl<Id> Default for KeyRegistry<Id> {
+    fn default() -> Self {
+        Self {
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<Id> KeyRegistry<Id> {
+    pub fn register<K: Into<String>>(&mut self, key: K, id: Id) -> Option<Id> {
+        self.keys.insert(key.into(), id)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Id> {
+        self.keys.get(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+impl<K: Into<String>, Id> FromIterator<(K, Id)> for KeyRegistry<Id> {
+    fn from_iter<T: IntoIterator<Item = (K, Id)>>(iter: T) -> Self {
+        let mut registry = Self::default();
+
+        for (key, id) in iter {
+            registry.register(key, id);
+        }
+
+        registry
+    }
+}
@@ -0,0 +1,44 @@
+use crate::data::{Data, NodeIndex};
+
+use std::collections::HashSet;
+
+/// A depth-first, pre-order walk over a subtree, yielding each node with its depth below the
+/// node the walk started from
+///
+/// Each node is only visited once, so cycles and shared children can't cause an infinite walk
+pub struct Walk<'a> {
+    data: &'a Data,
+    stack: Vec<(NodeIndex, usize)>,
+    visited: HashSet<NodeIndex>,
+}
+
+impl<'a> Walk<'a> {
+    pub(super) fn new(data: &'a Data, node_index: NodeIndex) -> Self {
+        Self {
+            data,
+            stack: vec![(node_index, 0)],
+            visited: HashSet::new(),
+        }
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = (NodeIndex, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node_index, depth)) = self.stack.pop() {
+            if !self.data.is_valid(node_index) || !self.visited.insert(node_index) {
+                continue;
+            }
+
+            if let Some(children) = self.data.get_children(node_index) {
+                self.stack
+                    .extend(children.iter().rev().map(|&child| (child, depth + 1)));
+            }
+
+            return Some((node_index, depth));
+        }
+
+        None
+    }
+}
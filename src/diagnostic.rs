@@ -0,0 +1,214 @@
+use crate::data::{Data, SourceIndex};
+use crate::lint::Lint;
+use crate::parse::error::ParseError;
+use crate::reporting::{self, ReportColors, ReportData, Reportable, Span};
+
+use std::{collections::HashMap, path::PathBuf};
+
+/// Anything that can be reported against a source
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Diagnostic {
+    Parse(ParseError),
+    Lint(Lint),
+}
+
+/// A diagnostic with everything it refers to rendered up front, so it no longer depends on the
+/// source it came from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResolvedDiagnostic {
+    pub kind: &'static str,
+    pub message: String,
+    pub notes: Vec<String>,
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    /// The full colorless report, as `Reportable::report` would render it
+    pub snippet: String,
+}
+
+impl Diagnostic {
+    /// The report kind this diagnostic is rendered with
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Diagnostic::Parse(error) if error.is_warning() => "WARNING",
+            Diagnostic::Parse(_) => "ERROR",
+            Diagnostic::Lint(_) => "WARNING",
+        }
+    }
+
+    pub fn resolve(&self, source: &str, path: &str) -> ResolvedDiagnostic {
+        let (line, column) = reporting::line_and_column(source, self.span().start_as_usize());
+
+        let mut report_data = ReportData::new(
+            source,
+            self.kind(),
+            path,
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        self.report(&mut report_data);
+
+        ResolvedDiagnostic {
+            kind: self.kind(),
+            message: self.message().unwrap_or_default(),
+            notes: self.notes(),
+            path: path.to_owned(),
+            line,
+            column,
+            snippet: report_data.take_errors().concat(),
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Diagnostic::Parse(error)
+    }
+}
+
+impl From<Lint> for Diagnostic {
+    fn from(lint: Lint) -> Self {
+        Diagnostic::Lint(lint)
+    }
+}
+
+impl Reportable<String, String> for Diagnostic {
+    fn span(&self) -> Span {
+        match self {
+            Diagnostic::Parse(error) => error.span(),
+            Diagnostic::Lint(lint) => lint.span(),
+        }
+    }
+
+    fn message(&self) -> Option<String> {
+        match self {
+            Diagnostic::Parse(error) => error.message(),
+            Diagnostic::Lint(lint) => lint.message(),
+        }
+    }
+
+    fn notes(&self) -> Vec<String> {
+        match self {
+            Diagnostic::Parse(error) => error.notes(),
+            Diagnostic::Lint(lint) => lint.notes(),
+        }
+    }
+
+    fn labels(&self) -> Vec<(Span, String)> {
+        match self {
+            Diagnostic::Parse(error) => error.labels(),
+            Diagnostic::Lint(lint) => lint.labels(),
+        }
+    }
+}
+
+/// Renders diagnostics from any number of sources as one colorless block, ordered by path and then
+/// by where they start in their source
+///
+/// Diagnostics whose source isn't in `data`, or has no path in `paths`, are left out
+pub fn render_all(
+    diagnostics: &[(SourceIndex, Diagnostic)],
+    data: &Data,
+    paths: &HashMap<SourceIndex, PathBuf>,
+) -> String {
+    let mut sorted = diagnostics
+        .iter()
+        .filter_map(|(source_index, diagnostic)| {
+            Some((
+                paths.get(source_index)?,
+                data.get_source(*source_index)?,
+                diagnostic,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    sorted.sort_by_key(|(path, _source, diagnostic)| (*path, diagnostic.span().start_as_usize()));
+
+    let mut output = String::new();
+
+    for (path, source, diagnostic) in sorted {
+        let mut report_data = ReportData::new(
+            source,
+            diagnostic.kind(),
+            path.display(),
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        diagnostic.report(&mut report_data);
+
+        output.extend(report_data.take_errors());
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::LintKind;
+
+    #[test]
+    fn render_all_orders_diagnostics_by_path_then_position() {
+        let mut data = Data::default();
+        let ships = data.insert_source("ship A\n\tcost\n\tmass 1.\n".to_owned());
+        let outfits = data.insert_source("outfit B\n\tmass\n".to_owned());
+
+        let paths = HashMap::from([
+            (ships, PathBuf::from("a/ships.txt")),
+            (outfits, PathBuf::from("b/outfits.txt")),
+        ]);
+
+        let missing_value = |key: &str, span| {
+            Diagnostic::from(Lint::new(
+                LintKind::MissingValue {
+                    key: key.to_owned(),
+                },
+                span,
+            ))
+        };
+
+        let outfit_mass = missing_value("mass", Span::new(10, 14));
+        let ship_cost = missing_value("cost", Span::new(8, 12));
+        let ship_mass = Diagnostic::from(Lint::new(
+            LintKind::SuspiciousNumber {
+                suggestion: "1.0".to_owned(),
+            },
+            Span::new(19, 21),
+        ));
+
+        let rendered = render_all(
+            &[
+                (outfits, outfit_mass.clone()),
+                (ships, ship_mass.clone()),
+                (ships, ship_cost.clone()),
+            ],
+            &data,
+            &paths,
+        );
+
+        let headers = rendered
+            .lines()
+            .filter(|line| line.contains(".txt:"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            headers,
+            ["a/ships.txt:2:1", "a/ships.txt:3:6", "b/outfits.txt:2:1"]
+        );
+
+        let ships_source = data.get_source(ships).unwrap();
+        let outfits_source = data.get_source(outfits).unwrap();
+
+        assert_eq!(
+            rendered,
+            [
+                ship_cost.resolve(ships_source, "a/ships.txt").snippet,
+                ship_mass.resolve(ships_source, "a/ships.txt").snippet,
+                outfit_mass.resolve(outfits_source, "b/outfits.txt").snippet,
+            ]
+            .concat()
+        );
+    }
+}
@@ -1,28 +1,89 @@
+pub mod block;
 pub mod error;
 pub mod token;
 
 use self::{
     error::{LexError, LexErrorKind},
-    token::{Token, TokenKind},
+    token::{SymbolClass, Token, TokenKind},
 };
 
 use crate::data::{Data, SourceIndex};
 use crate::reporting::Span;
 
+use std::ops::Range;
+
 type LexItem = Result<Token, LexError>;
 
 pub struct Lexer {
     source_index: SourceIndex,
     lookahead: Option<LexItem>,
+    // a token found along with an error, which comes right after that error
+    queued: Option<LexItem>,
     on_new_line: bool,
     byte_offset: usize,
     spaces: IndentKind,
+    line_continuation: bool,
+    lenient_indentation: bool,
+    warn_leading_zeros: bool,
+    // the widths of the indentation runs enclosing the current line, with lenient indentation
+    indent_widths: Vec<usize>,
+    // indents still to be returned for the current line, all with the same span
+    queued_indents: usize,
+    queued_indent_span: Span,
+    end: Option<usize>,
+    capture_trivia: bool,
+    trivia_start: usize,
+    comments: usize,
+}
+
+/// Counts of what a source lexes into, from `lex_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LexStats {
+    /// Every token, including indents and newlines
+    pub tokens: usize,
+    pub symbols: usize,
+    pub lines: usize,
+    pub comments: usize,
+    pub errors: usize,
+}
+
+/// Lexes the source at `source_index` to the end and counts what it found, without parsing it
+///
+/// A source that isn't in `data` has nothing to count
+pub fn lex_stats(data: &Data, source_index: SourceIndex) -> LexStats {
+    let Some(source) = data.get_source(source_index) else {
+        return LexStats::default();
+    };
+
+    let mut lexer = Lexer::new(source_index);
+    let mut stats = LexStats {
+        lines: source.lines().count(),
+        ..LexStats::default()
+    };
+
+    lexer.for_each_token(data, |item| match item {
+        Ok(token) => {
+            stats.tokens += 1;
+
+            if let TokenKind::Symbol = token.kind() {
+                stats.symbols += 1;
+            }
+        }
+        Err(_) => stats.errors += 1,
+    });
+
+    stats.comments = lexer.comments;
+
+    stats
 }
 
-enum IndentKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndentKind {
     Space,
     Tab,
+    /// No indentation has been seen yet
     Unknown,
+    /// Both tabs and spaces were used, which is reported as an error
     Mixed,
 }
 
@@ -31,15 +92,95 @@ impl Lexer {
         Self {
             source_index,
             lookahead: None,
+            queued: None,
             on_new_line: true,
             byte_offset: 0,
             spaces: IndentKind::Unknown,
+            line_continuation: false,
+            lenient_indentation: false,
+            warn_leading_zeros: false,
+            indent_widths: Vec::new(),
+            queued_indents: 0,
+            queued_indent_span: Span::new(0, 0),
+            end: None,
+            capture_trivia: false,
+            trivia_start: 0,
+            comments: 0,
         }
     }
 
+    /// When enabled, a `\` that is the last non-whitespace character on a line joins that line
+    /// with the next one, so no `Newline` token is produced between them
+    pub fn line_continuation(mut self, line_continuation: bool) -> Self {
+        self.line_continuation = line_continuation;
+        self
+    }
+
+    /// When enabled, tabs and spaces can both be used to indent without a `MixedIndentation`
+    /// error
+    ///
+    /// Each line's leading whitespace is read as one run, with a tab as wide as four spaces. A
+    /// run wider than the line above it is one level deeper however much wider it is, and a
+    /// narrower run goes back to the deepest enclosing line that isn't wider than it, so `\tb`
+    /// and `    c` on consecutive lines are siblings. Blank and comment-only lines are ignored.
+    pub fn lenient_indentation(mut self, lenient_indentation: bool) -> Self {
+        self.lenient_indentation = lenient_indentation;
+        self
+    }
+
+    /// When enabled, numbers with leading zeros, such as `007`, are reported with a
+    /// `LeadingZeros` warning before their token
+    pub fn warn_leading_zeros(mut self, warn_leading_zeros: bool) -> Self {
+        self.warn_leading_zeros = warn_leading_zeros;
+        self
+    }
+
+    /// When enabled, every symbol records everything between it and the previous symbol as its
+    /// `Token::leading_trivia`, such as whitespace, comments and quotes
+    pub fn capture_trivia(mut self, capture_trivia: bool) -> Self {
+        self.capture_trivia = capture_trivia;
+        self
+    }
+
+    /// Starts lexing at `start_offset` instead of the beginning of the source
+    ///
+    /// The offset must be on a char boundary, and should be at the start of a line since the
+    /// lexer assumes it is. Spans are still relative to the start of the source.
+    pub fn new_at(source_index: SourceIndex, start_offset: usize) -> Self {
+        Self {
+            byte_offset: start_offset,
+            trivia_start: start_offset,
+            ..Self::new(source_index)
+        }
+    }
+
+    /// Starts the trivia of the first symbol at `offset` instead of where lexing starts
+    pub(crate) fn trivia_from(&mut self, offset: usize) {
+        self.trivia_start = offset;
+    }
+
+    /// Stops lexing at `end`, even if the source continues past it
+    pub(crate) fn stop_at(&mut self, end: usize) {
+        self.end = Some(self.end.map_or(end, |current| current.min(end)));
+    }
+
     pub fn source_index(&self) -> SourceIndex {
         self.source_index
     }
+
+    /// Everything after the last symbol, if trivia is being captured, which is only final once
+    /// the source has been fully lexed
+    pub fn trailing_trivia(&self, data: &Data) -> Option<Span> {
+        let end = self.lexable_source(data)?.len();
+
+        self.capture_trivia
+            .then(|| Span::new(self.trivia_start.min(end), end))
+    }
+
+    /// The indentation used by the source so far, which is only final once it's been fully lexed
+    pub fn detected_indent(&self) -> IndentKind {
+        self.spaces
+    }
 }
 
 impl Lexer {
@@ -55,17 +196,30 @@ impl Lexer {
         self.advance(data)
     }
 
+    /// Lexes the rest of the source, calling `f` on every token and error in order without
+    /// collecting them
+    pub fn for_each_token(&mut self, data: &Data, mut f: impl FnMut(LexItem)) {
+        while let Some(item) = self.next(data) {
+            f(item);
+        }
+    }
+
     fn advance(&mut self, data: &Data) -> Option<LexItem> {
         if let Some(lookahead) = self.lookahead.take() {
             return Some(lookahead);
         }
 
-        while let Some(c) = data
-            .get_source(self.source_index())
-            .into_iter()
-            .flat_map(|source| source[(self.byte_offset)..].chars())
-            .next()
-        {
+        if let Some(queued) = self.queued.take() {
+            return Some(queued);
+        }
+
+        if self.queued_indents > 0 {
+            self.queued_indents -= 1;
+
+            return Some(Ok(Token::new(TokenKind::Indent, self.queued_indent_span)));
+        }
+
+        while let Some(c) = self.peek_char(data) {
             let start = self.byte_offset;
 
             self.byte_offset += c.len_utf8();
@@ -74,17 +228,69 @@ impl Lexer {
                 '\n' => {
                     self.on_new_line = true;
 
+                    if self.lenient_indentation
+                        && !matches!(
+                            self.peek_char(data),
+                            None | Some(' ' | '\t' | '\n' | '\r' | '#')
+                        )
+                    {
+                        self.indent_widths.clear();
+                    }
+
                     return Some(Ok(Token::new(
                         TokenKind::Newline,
                         Span::new(start, self.byte_offset),
                     )));
                 }
+                ' ' | '\t' if self.on_new_line && self.lenient_indentation => {
+                    let mut width = 0;
+
+                    self.byte_offset = start;
+
+                    while let Some(n @ (' ' | '\t')) = self.peek_char(data) {
+                        self.byte_offset += 1;
+
+                        let kind = if n == '\t' {
+                            width += LENIENT_TAB_WIDTH;
+                            IndentKind::Tab
+                        } else {
+                            width += 1;
+                            IndentKind::Space
+                        };
+
+                        self.spaces = match self.spaces {
+                            IndentKind::Unknown => kind,
+                            spaces if spaces == kind => kind,
+                            _ => IndentKind::Mixed,
+                        };
+                    }
+
+                    if matches!(self.peek_char(data), None | Some('\n' | '\r' | '#')) {
+                        continue;
+                    }
+
+                    while self.indent_widths.last().is_some_and(|&top| top > width) {
+                        self.indent_widths.pop();
+                    }
+
+                    if self.indent_widths.last().is_none_or(|&top| top < width) {
+                        self.indent_widths.push(width);
+                    }
+
+                    let span = Span::new(start, self.byte_offset);
+
+                    self.queued_indents = self.indent_widths.len() - 1;
+                    self.queued_indent_span = span;
+
+                    return Some(Ok(Token::new(TokenKind::Indent, span)));
+                }
                 ' ' if self.on_new_line => {
                     let token = Token::new(TokenKind::Indent, Span::new(start, self.byte_offset));
 
                     if let IndentKind::Tab = self.spaces {
                         self.spaces = IndentKind::Mixed;
-                        self.lookahead = Some(Ok(token));
+
+                        self.queued = Some(Ok(token));
 
                         return Some(Err(LexError::new(
                             LexErrorKind::MixedIndentation,
@@ -101,7 +307,8 @@ impl Lexer {
 
                     if let IndentKind::Space = self.spaces {
                         self.spaces = IndentKind::Mixed;
-                        self.lookahead = Some(Ok(token));
+
+                        self.queued = Some(Ok(token));
 
                         return Some(Err(LexError::new(
                             LexErrorKind::MixedIndentation,
@@ -114,12 +321,13 @@ impl Lexer {
                     return Some(Ok(token));
                 }
                 ' ' | '\t' => {}
+                '\\' if let Some(next_line) = self.continued_line(data, start) => {
+                    self.byte_offset = next_line;
+                }
                 '#' => {
-                    while let Some(n) = data
-                        .get_source(self.source_index())
-                        .into_iter()
-                        .flat_map(|source| source[(self.byte_offset)..].chars())
-                        .next()
+                    self.comments += 1;
+
+                    while let Some(n) = self.peek_char(data)
                         && n != '\n'
                     {
                         self.byte_offset += n.len_utf8();
@@ -130,11 +338,7 @@ impl Lexer {
 
                     let after_quote = self.byte_offset;
 
-                    while let Some(n) = data
-                        .get_source(self.source_index())
-                        .into_iter()
-                        .flat_map(|source| source[(self.byte_offset)..].chars())
-                        .next()
+                    while let Some(n) = self.peek_char(data)
                         && n != '\n'
                         && n != c
                     {
@@ -142,45 +346,97 @@ impl Lexer {
                     }
 
                     let token =
-                        Token::new(TokenKind::Symbol, Span::new(after_quote, self.byte_offset));
+                        Token::new(TokenKind::Symbol, Span::new(after_quote, self.byte_offset))
+                            .with_symbol_class(SymbolClass::Quoted);
 
-                    if let Some(n) = data
-                        .get_source(self.source_index())
-                        .into_iter()
-                        .flat_map(|source| source[(self.byte_offset)..].chars())
-                        .next()
-                        && n != c
+                    if let Some(n) = self.peek_char(data)
+                        && n == c
                     {
-                        self.lookahead = Some(Ok(token));
+                        self.byte_offset += c.len_utf8();
+
+                        return Some(Ok(self.with_trivia(token)));
+                    }
+
+                    let other_quote = if c == '"' { '`' } else { '"' };
+
+                    // an unclosed string with the other quote in it was most likely meant to be
+                    // closed by it, but like any unclosed string it still runs to the end of the line
+                    let line = self
+                        .lexable_source(data)
+                        .map_or("", |source| &source[after_quote..self.byte_offset]);
+
+                    if let Some(i) = line.rfind(other_quote) {
+                        let closing_quote = after_quote + i;
+
+                        // a quote ending the line is the closer, so it's left out of the string
+                        let token = if line[i + other_quote.len_utf8()..].trim().is_empty() {
+                            token.with_span(Span::new(after_quote, closing_quote))
+                        } else {
+                            token
+                        };
+
+                        self.queued = Some(Ok(self.with_trivia(token)));
 
                         return Some(Err(LexError::new(
-                            LexErrorKind::UnclosedString,
+                            LexErrorKind::MismatchedQuote { closing_quote },
                             Span::new(start, after_quote),
                         )));
                     } else {
-                        self.byte_offset += c.len_utf8();
+                        self.queued = Some(Ok(self.with_trivia(token)));
 
-                        return Some(Ok(token));
+                        return Some(Err(LexError::new(
+                            LexErrorKind::UnclosedString {
+                                content_end: self.byte_offset,
+                            },
+                            Span::new(start, after_quote),
+                        )));
                     }
                 }
+                _ if is_control_character(c) => {
+                    self.on_new_line = false;
+
+                    return Some(Err(LexError::new(
+                        LexErrorKind::ControlCharacter { byte: c as u8 },
+                        Span::new(start, self.byte_offset),
+                    )));
+                }
                 _ if c.is_ascii() => {
                     self.on_new_line = false;
 
-                    while let Some(n) = data
-                        .get_source(self.source_index())
-                        .into_iter()
-                        .flat_map(|source| source[(self.byte_offset)..].chars())
-                        .next()
+                    while let Some(n) = self.peek_char(data)
                         && !n.is_ascii_whitespace()
                         && n.is_ascii()
+                        && !is_control_character(n)
+                        && !(n == '\\' && self.continued_line(data, self.byte_offset).is_some())
                     {
                         self.byte_offset += n.len_utf8();
                     }
 
-                    return Some(Ok(Token::new(
-                        TokenKind::Symbol,
-                        Span::new(start, self.byte_offset),
-                    )));
+                    let lexeme = self
+                        .lexable_source(data)
+                        .and_then(|source| source.get(start..self.byte_offset));
+
+                    let symbol_class =
+                        lexeme.map_or(SymbolClass::Identifier, SymbolClass::of_unquoted);
+
+                    let token = Token::new(TokenKind::Symbol, Span::new(start, self.byte_offset))
+                        .with_symbol_class(symbol_class);
+
+                    if self.warn_leading_zeros
+                        && symbol_class == SymbolClass::Number
+                        && let Some(leading_zeros) = lexeme.and_then(leading_zeros)
+                    {
+                        self.queued = Some(Ok(self.with_trivia(token)));
+
+                        return Some(Err(LexError::new(
+                            LexErrorKind::LeadingZeros {
+                                number_start: start + leading_zeros.end,
+                            },
+                            Span::new(start, self.byte_offset),
+                        )));
+                    }
+
+                    return Some(Ok(self.with_trivia(token)));
                 }
                 _ => {
                     self.on_new_line = false;
@@ -195,4 +451,277 @@ impl Lexer {
 
         None
     }
+
+    // gives a symbol everything since the previous symbol as its leading trivia, if capturing it
+    fn with_trivia(&mut self, token: Token) -> Token {
+        if !self.capture_trivia {
+            return token;
+        }
+
+        let start = token.span().start_as_usize();
+
+        self.trivia_start = self.trivia_start.min(start);
+
+        let token = token.with_leading_trivia(Span::new(self.trivia_start, start));
+
+        self.trivia_start = token.span().end_as_usize();
+
+        token
+    }
+
+    fn lexable_source<'a>(&self, data: &'a Data) -> Option<&'a str> {
+        let source = data.get_source(self.source_index())?;
+
+        match self.end {
+            Some(end) => source.get(..end),
+            None => Some(source),
+        }
+    }
+
+    fn peek_char(&self, data: &Data) -> Option<char> {
+        self.lexable_source(data)?
+            .get(self.byte_offset..)?
+            .chars()
+            .next()
+    }
+
+    // the offset just past the newline, if line continuation is enabled and the backslash at
+    // `offset` is followed by nothing but whitespace on its line
+    fn continued_line(&self, data: &Data, offset: usize) -> Option<usize> {
+        if !self.line_continuation {
+            return None;
+        }
+
+        let rest = self.lexable_source(data)?.get(offset..)?;
+        let rest = rest.strip_prefix('\\')?;
+        let newline = rest.find('\n')?;
+
+        rest[..newline]
+            .chars()
+            .all(|ch| ch.is_ascii_whitespace())
+            .then_some(offset + 1 + newline + 1)
+    }
+}
+
+// the range of the leading zeros of a number, after any sign, keeping a single zero when every
+// digit before the point is one
+fn leading_zeros(number: &str) -> Option<Range<usize>> {
+    let sign_len = usize::from(number.starts_with(['-', '+']));
+    let digits = &number[sign_len..];
+
+    let zeros = digits.len() - digits.trim_start_matches('0').len();
+    let zeros = if digits[zeros..].starts_with(|ch: char| ch.is_ascii_digit()) {
+        zeros
+    } else {
+        zeros.saturating_sub(1)
+    };
+
+    (zeros > 0).then_some(sign_len..sign_len + zeros)
+}
+
+// how many spaces a tab counts as when comparing lenient indentation runs
+const LENIENT_TAB_WIDTH: usize = 4;
+
+// carriage returns are left to be read as they always have been, since they end every line of
+// files with windows line endings
+fn is_control_character(c: char) -> bool {
+    c.is_ascii_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporting::{ReportColors, ReportData, Reportable};
+
+    // the span of every leading zeros warning in `source`
+    fn leading_zero_warnings(source: &str) -> Vec<(usize, usize)> {
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut lexer = Lexer::new(source_index).warn_leading_zeros(true);
+        let mut warnings = vec![];
+
+        lexer.for_each_token(&data, |item| {
+            if let Err(error) = item
+                && let LexErrorKind::LeadingZeros { number_start } = error.kind()
+            {
+                warnings.push((error.span().start_as_usize(), number_start));
+            }
+        });
+
+        warnings
+    }
+
+    // the lexeme of every symbol in `source`, along with the quote of any mismatched quote error
+    fn lex_mismatched(source: &str) -> (Vec<String>, Vec<usize>) {
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut symbols = vec![];
+        let mut closing_quotes = vec![];
+
+        Lexer::new(source_index).for_each_token(&data, |item| match item {
+            Ok(token) if token.kind() == TokenKind::Symbol => {
+                symbols.push(token.lexeme(source).unwrap().to_owned());
+            }
+            Err(error) => {
+                if let LexErrorKind::MismatchedQuote { closing_quote } = error.kind() {
+                    closing_quotes.push(closing_quote);
+                }
+            }
+            Ok(_) => {}
+        });
+
+        (symbols, closing_quotes)
+    }
+
+    #[test]
+    fn mismatched_quotes_end_at_the_other_quote() {
+        assert_eq!(
+            lex_mismatched("a \"foo`\nb\n"),
+            (
+                vec!["a".to_owned(), "foo".to_owned(), "b".to_owned()],
+                vec![6]
+            )
+        );
+        assert_eq!(
+            lex_mismatched("a `bar\"\nb\n"),
+            (
+                vec!["a".to_owned(), "bar".to_owned(), "b".to_owned()],
+                vec![6]
+            )
+        );
+    }
+
+    #[test]
+    fn mismatched_quotes_inside_the_line_run_to_its_end() {
+        assert_eq!(
+            lex_mismatched("a \"He said `hi` ok\n"),
+            (vec!["a".to_owned(), "He said `hi` ok".to_owned()], vec![14])
+        );
+    }
+
+    #[test]
+    fn unclosed_strings_report_where_they_opened_and_where_they_ran_to() {
+        let source = "ship A\n\tname \"Heavy Shuttle\n";
+
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut errors = vec![];
+
+        Lexer::new(source_index).for_each_token(&data, |item| {
+            if let Err(error) = item {
+                errors.push(error);
+            }
+        });
+
+        let [error] = errors.as_slice() else {
+            panic!("expected one error, got {errors:?}");
+        };
+
+        assert_eq!(
+            error.kind(),
+            LexErrorKind::UnclosedString {
+                content_end: source.len() - 1
+            }
+        );
+
+        let mut report_data = ReportData::new(
+            source,
+            "ERROR",
+            "ships.txt",
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        error.report(&mut report_data);
+
+        let report = report_data.take_errors().concat();
+
+        assert!(report.contains("ships.txt:2:6\nERROR: This string was never closed\n"));
+        assert!(report.contains("NOTE: The string content ran until here (ships.txt:2:20)\n"));
+    }
+
+    #[test]
+    fn control_characters_are_reported_and_skipped() {
+        let source = "a \0b\nc\x07 d\n";
+
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut symbols = vec![];
+        let mut errors = vec![];
+
+        Lexer::new(source_index).for_each_token(&data, |item| match item {
+            Ok(token) if token.kind() == TokenKind::Symbol => {
+                symbols.push(token.lexeme(source).unwrap().to_owned());
+            }
+            Ok(_) => {}
+            Err(error) => errors.push((error.kind(), error.span())),
+        });
+
+        assert_eq!(symbols, ["a", "b", "c", "d"]);
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::ControlCharacter { byte: 0 }, Span::new(2, 3)),
+                (LexErrorKind::ControlCharacter { byte: 7 }, Span::new(6, 7)),
+            ]
+        );
+
+        let (kind, span) = errors[1];
+
+        assert_eq!(
+            LexError::new(kind, span).notes(),
+            ["This is the byte 0x07, which was skipped"]
+        );
+    }
+
+    #[test]
+    fn lex_stats_counts_tokens_comments_and_errors() {
+        let mut data = Data::default();
+        let source_index =
+            data.insert_source("# header\nship A # trailing\n\tcost \u{e9}\n".to_owned());
+
+        assert_eq!(
+            lex_stats(&data, source_index),
+            LexStats {
+                tokens: 7,
+                symbols: 3,
+                lines: 3,
+                comments: 2,
+                errors: 1,
+            }
+        );
+
+        let mut other = Data::default();
+        other.insert_source("a\n".to_owned());
+        let missing = other.insert_source("b\n".to_owned());
+
+        assert_eq!(lex_stats(&data, missing), LexStats::default());
+    }
+
+    #[test]
+    fn leading_zeros_are_flagged() {
+        assert_eq!(leading_zero_warnings("a 007\n"), [(2, 4)]);
+        assert_eq!(leading_zero_warnings("a -00.5\n"), [(2, 4)]);
+    }
+
+    #[test]
+    fn numbers_without_leading_zeros_are_not_flagged() {
+        assert!(leading_zero_warnings("a 0 0.5 70 -0 \"007\" x007\n").is_empty());
+    }
+
+    #[test]
+    fn leading_zeros_are_not_flagged_by_default() {
+        let mut data = Data::default();
+        let source_index = data.insert_source("a 007\n".to_owned());
+
+        let mut errors = 0;
+
+        Lexer::new(source_index).for_each_token(&data, |item| errors += usize::from(item.is_err()));
+
+        assert_eq!(errors, 0);
+    }
 }
@@ -0,0 +1,145 @@
+use crate::data::Data;
+use crate::lex::{
+    LexItem, Lexer,
+    token::{Token, TokenKind},
+};
+use crate::reporting::Span;
+
+use std::collections::VecDeque;
+
+/// Wraps a `Lexer` so that indentation comes out as balanced blocks instead of one `Indent` per
+/// indentation character
+///
+/// A line indented deeper than the one above it opens a block with one `Indent` spanning its
+/// indentation, and each block a line is no longer indented under is closed with an empty
+/// `Dedent` at the start of that line. Every block still open is closed at the end of the source.
+///
+/// Blocks follow the same indentation counts the parser uses, so a line indented less than the
+/// line above it but more than the line before that closes one block and opens another. Lines
+/// that are blank or only a comment don't change the indentation.
+pub struct BlockLexer {
+    lexer: Lexer,
+    levels: Vec<usize>,
+    pending: VecDeque<LexItem>,
+    lookahead: Option<LexItem>,
+    on_new_line: bool,
+    end: usize,
+}
+
+impl BlockLexer {
+    pub fn new(lexer: Lexer) -> Self {
+        Self {
+            lexer,
+            levels: vec![0],
+            pending: VecDeque::new(),
+            lookahead: None,
+            on_new_line: true,
+            end: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> Lexer {
+        self.lexer
+    }
+}
+
+impl BlockLexer {
+    pub fn peek(&mut self, data: &Data) -> Option<&LexItem> {
+        if self.lookahead.is_none() {
+            self.lookahead = self.advance(data);
+        }
+
+        self.lookahead.as_ref()
+    }
+
+    pub fn next(&mut self, data: &Data) -> Option<LexItem> {
+        self.advance(data)
+    }
+
+    fn advance(&mut self, data: &Data) -> Option<LexItem> {
+        if let Some(lookahead) = self.lookahead.take() {
+            return Some(lookahead);
+        }
+
+        if let Some(item) = self.pending.pop_front() {
+            return Some(item);
+        }
+
+        // the indentation of the current line, which is only known once its first symbol is seen
+        let mut level = 0;
+        let mut indentation: Option<Span> = None;
+
+        loop {
+            let Some(item) = self.lexer.next(data) else {
+                self.close_blocks(0, Span::new(self.end, self.end));
+
+                return self.pending.pop_front();
+            };
+
+            let token = match item {
+                Ok(token) => token,
+                Err(error) if self.on_new_line => {
+                    self.pending.push_back(Err(error));
+                    continue;
+                }
+                Err(error) => return Some(Err(error)),
+            };
+
+            self.end = token.span().end_as_usize();
+
+            match token.kind() {
+                TokenKind::Indent if self.on_new_line => {
+                    level += 1;
+                    indentation = Some(match indentation {
+                        Some(span) => Span::new(span.start_as_usize(), token.span().end_as_usize()),
+                        None => token.span(),
+                    });
+                }
+                TokenKind::Newline if self.on_new_line => {
+                    // blank lines keep the indentation of the line above them
+                    level = 0;
+                    indentation = None;
+
+                    self.pending.push_back(Ok(token));
+                }
+                TokenKind::Newline => {
+                    self.on_new_line = true;
+
+                    return Some(Ok(token));
+                }
+                TokenKind::Symbol if self.on_new_line => {
+                    self.on_new_line = false;
+
+                    let line_start = token.span().start_as_usize();
+
+                    self.close_blocks(level, Span::new(line_start, line_start));
+
+                    if self.levels.last().is_some_and(|&current| level > current) {
+                        self.levels.push(level);
+
+                        let span = indentation.unwrap_or(Span::new(line_start, line_start));
+
+                        self.pending
+                            .push_back(Ok(Token::new(TokenKind::Indent, span)));
+                    }
+
+                    self.pending.push_back(Ok(token));
+
+                    return self.pending.pop_front();
+                }
+                TokenKind::Indent | TokenKind::Dedent | TokenKind::Symbol => {
+                    return Some(Ok(token));
+                }
+            }
+        }
+    }
+
+    // pushes a `Dedent` for every open block indented deeper than `level`
+    fn close_blocks(&mut self, level: usize, span: Span) {
+        while self.levels.last().is_some_and(|&current| current > level) {
+            self.levels.pop();
+            self.pending
+                .push_back(Ok(Token::new(TokenKind::Dedent, span)));
+        }
+    }
+}
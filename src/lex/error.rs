@@ -3,8 +3,33 @@ use crate::reporting::{Reportable, Span};
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum LexErrorKind {
     MixedIndentation,
-    UnclosedString,
+    UnclosedString {
+        content_end: usize,
+    },
+    /// A string that was never closed by its own quote, but has the other quote inside it, which
+    /// runs to the end of the line unless that quote ends the line
+    MismatchedQuote {
+        /// The last of the other quote on the line
+        closing_quote: usize,
+    },
     NonAsciiCharacter,
+    /// An ASCII control character other than a tab or newline, which is skipped
+    ControlCharacter {
+        byte: u8,
+    },
+    /// A number with leading zeros, such as `007`, which is a warning only reported when
+    /// `Lexer::warn_leading_zeros` is enabled
+    LeadingZeros {
+        /// Where the number starts once its leading zeros are ignored
+        number_start: usize,
+    },
+}
+
+impl LexErrorKind {
+    /// Whether this is a style warning rather than something wrong with the source
+    pub fn is_warning(&self) -> bool {
+        matches!(self, LexErrorKind::LeadingZeros { .. })
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -17,6 +42,10 @@ impl LexError {
     pub fn new(kind: LexErrorKind, span: Span) -> Self {
         Self { kind, span }
     }
+
+    pub fn kind(&self) -> LexErrorKind {
+        self.kind
+    }
 }
 
 impl Reportable<String, String> for LexError {
@@ -28,10 +57,17 @@ impl Reportable<String, String> for LexError {
         Some(
             match self.kind {
                 LexErrorKind::MixedIndentation => "Mixed indentation detected",
-                LexErrorKind::UnclosedString => "This string was never closed",
+                LexErrorKind::UnclosedString { .. } => "This string was never closed",
+                LexErrorKind::MismatchedQuote { .. } => {
+                    "This string was closed with a different quote than it was opened with"
+                }
                 LexErrorKind::NonAsciiCharacter => {
                     "Only ASCII characters are allowed in Endless Sky data files"
                 }
+                LexErrorKind::ControlCharacter { .. } => {
+                    "Control characters are not allowed in Endless Sky data files"
+                }
+                LexErrorKind::LeadingZeros { .. } => "This number has leading zeros",
             }
             .to_owned(),
         )
@@ -40,8 +76,31 @@ impl Reportable<String, String> for LexError {
     fn notes(&self) -> Vec<String> {
         match self.kind {
             LexErrorKind::MixedIndentation => vec!["You should only use one of tabs or spaces when indenting, not both".to_owned()],
-            LexErrorKind::UnclosedString => vec!["The string terminated at the newline character, but you should close it anyway".to_owned()],
+            LexErrorKind::UnclosedString { .. } => vec!["The string terminated at the newline character, but you should close it anyway".to_owned()],
+            LexErrorKind::MismatchedQuote { .. } => vec!["Strings opened with `\"` must be closed with `\"`, and strings opened with a backtick must be closed with a backtick".to_owned()],
             LexErrorKind::NonAsciiCharacter => vec!["If this has changed since Endless Sky RW was written, the library needs to be updated".to_owned()],
+            LexErrorKind::ControlCharacter { byte } => vec![format!("This is the byte {byte:#04x}, which was skipped")],
+            LexErrorKind::LeadingZeros { .. } => vec!["Leading zeros are ignored when the number is read".to_owned()],
+        }
+    }
+
+    fn labels(&self) -> Vec<(Span, String)> {
+        match self.kind {
+            LexErrorKind::UnclosedString { content_end } => vec![(
+                Span::new(content_end, content_end),
+                "The string content ran until here".to_owned(),
+            )],
+            LexErrorKind::MismatchedQuote { closing_quote } => vec![(
+                Span::new(closing_quote, closing_quote + 1),
+                "This quote was probably meant to close the string".to_owned(),
+            )],
+            LexErrorKind::LeadingZeros { number_start } => vec![(
+                Span::new(number_start, number_start),
+                "The number is read as starting here".to_owned(),
+            )],
+            LexErrorKind::MixedIndentation
+            | LexErrorKind::NonAsciiCharacter
+            | LexErrorKind::ControlCharacter { .. } => vec![],
         }
     }
 }
@@ -1,20 +1,77 @@
+use crate::data;
 use crate::reporting::{Span, Spannable};
 
+use std::fmt::{self, Display};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Token {
     kind: TokenKind,
     span: Span,
+    symbol_class: Option<SymbolClass>,
+    leading_trivia: Option<Span>,
 }
 
 impl Token {
     pub fn new(kind: TokenKind, span: Span) -> Self {
-        Self { kind, span }
+        Self {
+            kind,
+            span,
+            symbol_class: None,
+            leading_trivia: None,
+        }
+    }
+
+    pub fn with_symbol_class(mut self, symbol_class: SymbolClass) -> Self {
+        self.symbol_class = Some(symbol_class);
+        self
+    }
+
+    pub fn with_leading_trivia(mut self, leading_trivia: Span) -> Self {
+        self.leading_trivia = Some(leading_trivia);
+        self
+    }
+
+    /// The same token, moved to `span`
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// The same token with `f` applied to every offset of its span and trivia, such as to shift
+    /// it after an edit to its source
+    pub fn map_offsets(mut self, mut f: impl FnMut(usize) -> usize) -> Self {
+        self.span = Span::new(f(self.span.start_as_usize()), f(self.span.end_as_usize()));
+
+        if let Some(trivia) = self.leading_trivia {
+            self.leading_trivia = Some(Span::new(
+                f(trivia.start_as_usize()),
+                f(trivia.end_as_usize()),
+            ));
+        }
+
+        self
     }
 
     pub fn lexeme<'a>(&self, source: &'a str) -> Option<&'a str> {
         source.slice((self.span().start_as_usize())..(self.span().end_as_usize()))
     }
 
+    /// Like `lexeme`, but a span running past the end of `source` is clamped to it instead of
+    /// returning `None`
+    pub fn lexeme_clamped<'a>(&self, source: &'a str) -> Option<&'a str> {
+        let span = self.span().clamp_to(source.len());
+
+        source.slice((span.start_as_usize())..(span.end_as_usize()))
+    }
+
+    /// Shows the token's kind, lexeme and span, like `Symbol("ship" @ 0..4)`
+    pub fn debug_with<'a>(&self, source: &'a str) -> impl Display + 'a {
+        TokenDebug {
+            token: *self,
+            source,
+        }
+    }
+
     pub fn kind(&self) -> TokenKind {
         self.kind
     }
@@ -22,11 +79,69 @@ impl Token {
     pub fn span(&self) -> Span {
         self.span
     }
+
+    /// What kind of symbol the lexer found, which is `None` for any other token, and for tokens
+    /// that were made some other way, such as by a `DataBuilder`
+    pub fn symbol_class(&self) -> Option<SymbolClass> {
+        self.symbol_class
+    }
+
+    /// The text between this token and the symbol before it, if the lexer was capturing trivia
+    pub fn leading_trivia(&self) -> Option<Span> {
+        self.leading_trivia
+    }
+}
+
+struct TokenDebug<'a> {
+    token: Token,
+    source: &'a str,
+}
+
+impl Display for TokenDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = self.token.kind();
+        let span = self.token.span();
+
+        match self.token.lexeme(self.source) {
+            Some(lexeme) => write!(f, "{kind:?}({lexeme:?} @ {span})"),
+            None => write!(f, "{kind:?}(<out of bounds> @ {span})"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TokenKind {
     Symbol,
     Indent,
+    /// Closes a block opened by an `Indent`, which only `BlockLexer` produces
+    Dedent,
     Newline,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolClass {
+    /// Anything quoted, whatever its content
+    Quoted,
+    /// An unquoted symbol that parses as a number, like `5` or `-0.5`
+    Number,
+    /// An unquoted symbol made only of punctuation, like `>=` or `+=`
+    Operator,
+    /// Any other unquoted symbol, like `has`
+    Identifier,
+    /// Text written back exactly as it is in the source, quotes included, like a rest of line
+    /// value that starts or ends with a quoted symbol
+    Verbatim,
+}
+
+impl SymbolClass {
+    /// The class of an unquoted symbol
+    pub fn of_unquoted(lexeme: &str) -> Self {
+        if data::looks_like_number(lexeme) && lexeme.parse::<f64>().is_ok() {
+            Self::Number
+        } else if !lexeme.is_empty() && lexeme.chars().all(|ch| ch.is_ascii_punctuation()) {
+            Self::Operator
+        } else {
+            Self::Identifier
+        }
+    }
+}
@@ -1,31 +1,94 @@
 mod arena;
 mod data;
+mod diagnostic;
 mod lex;
+mod lint;
 mod macros;
 mod parse;
-mod reporting;
+pub mod reporting;
 
 pub mod prelude {
-    pub use crate::data::{Data, Node, NodeIndex, SourceIndex};
-    pub use crate::lex::token::{Token, TokenKind};
-    pub use crate::reporting::Span;
+    pub use crate::arena::{AllocationPolicy, LookupStatus};
+    pub use crate::data::{
+        Data, Node, NodeIndex, SourceIndex, WriteOptions,
+        builder::{DataBuilder, NodeRef},
+        condition::Condition,
+        edit::Edit,
+        offset_index::OffsetIndex,
+        registry::KeyRegistry,
+        walk::Walk,
+    };
+    pub use crate::diagnostic::{Diagnostic, ResolvedDiagnostic, render_all};
+    pub use crate::lex::{
+        IndentKind, LexStats, Lexer,
+        block::BlockLexer,
+        error::{LexError, LexErrorKind},
+        lex_stats,
+        token::{SymbolClass, Token, TokenKind},
+    };
+    pub use crate::lint::{Lint, LintKind};
+    pub use crate::parse::{
+        Parser,
+        error::{ParseError, ParseErrorKind, ParseFailure},
+    };
+    pub use crate::reporting::{
+        HeaderFormat, NotesPosition, ReportColor, ReportColors, ReportData, Reportable, Span,
+    };
 }
 
 pub use self::prelude::*;
 
-use crate::parse::Parser;
-use crate::reporting::{ReportColors, ReportData, Reportable};
-
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
-    io::{self, Write},
-    path::PathBuf,
+    io::{self, IsTerminal, Read, Write},
+    mem,
+    path::{Path, PathBuf},
 };
 
 const EXTENSION: &str = "txt";
 
+const GZIP_EXTENSION: &str = "gz";
+
+const MAX_UNTRUSTED_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ReadOptions {
+    /// Sources longer than this are rejected with a diagnostic instead of being parsed
+    pub max_source_bytes: Option<usize>,
+    /// Render every error from a source under one shared header, in source order
+    pub group_errors: bool,
+    /// Only reports the first errors of each source, followed by a line saying how many more
+    /// there were
+    pub max_errors_per_source: Option<usize>,
+    /// Overrides the width of the divider above each report
+    pub divider_width: Option<usize>,
+    /// Overrides how many lines of context are printed around each report
+    pub context_lines: Option<usize>,
+    pub notes_position: NotesPosition,
+    pub header_format: HeaderFormat,
+    /// Reads the file named by any root `include <path>` node, resolved against the directory of
+    /// the including file, and puts its root nodes in place of the `include` node, which is
+    /// dropped
+    ///
+    /// Files that were already read, including through another include, are skipped, which also
+    /// stops include cycles
+    pub follow_includes: bool,
+    /// Replaces every `\r\n` and lone `\r` with `\n` before a source is parsed
+    ///
+    /// Spans are then offsets into the normalized source, so they no longer match byte offsets
+    /// in the original file wherever a `\r` was removed before them
+    pub normalize_newlines: bool,
+}
+
 pub fn read_path<T: Into<PathBuf>>(path: T) -> Option<DataFolder> {
+    read_path_with_options(path, &ReadOptions::default())
+}
+
+pub fn read_path_with_options<T: Into<PathBuf>>(
+    path: T,
+    options: &ReadOptions,
+) -> Option<DataFolder> {
     let base_path = T::into(path);
 
     let mut paths = vec![];
@@ -33,10 +96,10 @@ pub fn read_path<T: Into<PathBuf>>(path: T) -> Option<DataFolder> {
 
     let file_path = base_path.clone();
 
-    if let ReadResult::Ok = read_source(file_path, &mut paths, &mut sources) {
-        let reader = Reader::new(paths, sources);
+    if let ReadResult::Ok = read_source(file_path, &mut paths, &mut sources, options) {
+        let reader = Reader::new(paths, sources, options);
 
-        match reader.read(&mut io::stdout(), true) {
+        match reader.read(&mut io::stdout(), io::stdout().is_terminal()) {
             Ok(data) => return Some(data),
             Err(error) => {
                 eprintln!("{error}");
@@ -48,13 +111,139 @@ pub fn read_path<T: Into<PathBuf>>(path: T) -> Option<DataFolder> {
     None
 }
 
+/// Like `read_path`, but returns every parse error as a `ResolvedDiagnostic` instead of printing
+/// it
+///
+/// Problems that aren't tied to a place in a source, such as files that can't be read, are still
+/// printed to stderr
+pub fn read_path_with_diagnostics<T: Into<PathBuf>>(
+    path: T,
+) -> Option<(DataFolder, Vec<ResolvedDiagnostic>)> {
+    let mut paths = vec![];
+    let mut sources = vec![];
+
+    let options = ReadOptions::default();
+
+    if let ReadResult::Ok = read_source(T::into(path), &mut paths, &mut sources, &options) {
+        let reader = Reader::new(paths, sources, &options);
+
+        let (data_folder, reports, diagnostics) = reader.read_diagnostics();
+
+        for report in reports {
+            eprint!("{report}");
+        }
+
+        return Some((data_folder, diagnostics));
+    }
+
+    None
+}
+
+pub fn read_reader<R: Read>(input: R, name: &str) -> io::Result<(DataFolder, Vec<String>)> {
+    read_reader_with_options(input, name, &ReadOptions::default())
+}
+
+pub fn read_reader_with_options<R: Read>(
+    input: R,
+    name: &str,
+    options: &ReadOptions,
+) -> io::Result<(DataFolder, Vec<String>)> {
+    let Some(source) = read_limited(input, options.max_source_bytes)? else {
+        let mut reader = Reader::new(vec![], vec![], options);
+
+        reader.rejected.push(format!(
+            "{}\n",
+            source_too_large(
+                Path::new(name),
+                options.max_source_bytes.unwrap_or_default()
+            )
+        ));
+
+        return Ok(reader.read_reports(false));
+    };
+
+    let reader = Reader::new(vec![PathBuf::from(name)], vec![source], options);
+
+    Ok(reader.read_reports(false))
+}
+
+pub fn read_bytes(bytes: &[u8], name: &str) -> (DataFolder, Vec<String>) {
+    read_bytes_with_options(bytes, name, &ReadOptions::default())
+}
+
+/// Reads a source that is expected to be ASCII, such as one embedded with `include_bytes!`
+///
+/// Non-ASCII bytes get a diagnostic giving the offset of the first one, and bytes that aren't
+/// valid UTF-8 are replaced before parsing, with another diagnostic saying so
+pub fn read_bytes_with_options(
+    bytes: &[u8],
+    name: &str,
+    options: &ReadOptions,
+) -> (DataFolder, Vec<String>) {
+    let mut rejected = vec![];
+
+    if let Some(offset) = bytes.iter().position(|byte| !byte.is_ascii()) {
+        rejected.push(format!(
+            "File \"{name}\" is not ASCII, starting at byte {offset}\n"
+        ));
+    }
+
+    let source = match String::from_utf8(bytes.to_vec()) {
+        Ok(source) => source,
+        Err(error) => {
+            rejected.push(format!(
+                "File \"{name}\" is not valid UTF-8, so its invalid bytes were replaced\n"
+            ));
+
+            String::from_utf8_lossy(error.as_bytes()).into_owned()
+        }
+    };
+
+    let mut reader = Reader::new(vec![PathBuf::from(name)], vec![source], options);
+
+    reader.rejected.extend(rejected);
+
+    reader.read_reports(false)
+}
+
+/// Parses a source that can't be trusted, such as fuzzer or upload input, failing instead of
+/// panicking on input the parser can't handle
+///
+/// Sources too long for a `Span` and nodes nested more than 128 levels deep are rejected
+pub fn try_parse_str(source: &str) -> Result<(Data, Vec<ParseError>), ParseFailure> {
+    if u32::try_from(source.len()).is_err() {
+        return Err(ParseFailure::SourceTooLarge { len: source.len() });
+    }
+
+    let mut data = Data::default();
+    let source_index = data.insert_source(source.to_owned());
+
+    let mut parser = Parser::new(source_index).max_depth(MAX_UNTRUSTED_DEPTH);
+
+    parser.parse(&mut data);
+
+    match parser.take_failure() {
+        Some(failure) => Err(failure),
+        None => Ok((data, parser.take_errors())),
+    }
+}
+
 #[cfg(all(target_family = "wasm", target_os = "unknown"))]
 pub fn read_upload(paths: Vec<String>, sources: Vec<String>) -> Option<(DataFolder, Vec<u8>)> {
+    read_upload_with_options(paths, sources, &ReadOptions::default())
+}
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+pub fn read_upload_with_options(
+    paths: Vec<String>,
+    sources: Vec<String>,
+    options: &ReadOptions,
+) -> Option<(DataFolder, Vec<u8>)> {
     let mut error_buffer = vec![];
 
     let paths = paths.into_iter().map(PathBuf::from).collect::<Vec<_>>();
 
-    let reader = Reader::new(paths, sources);
+    let reader = Reader::new(paths, sources, options);
 
     match reader.read(&mut error_buffer, false) {
         Ok(data) => Some((data, error_buffer)),
@@ -62,6 +251,150 @@ pub fn read_upload(paths: Vec<String>, sources: Vec<String>) -> Option<(DataFold
     }
 }
 
+pub fn validate_path<T: Into<PathBuf>>(path: T) -> Vec<(PathBuf, Vec<String>)> {
+    validate_path_with_options(path, &ReadOptions::default())
+}
+
+/// Parses every data file under `path` only to collect its diagnostics, returning them by file
+///
+/// Each file is read and parsed on its own, and its data is dropped before the next one is read.
+/// Files without any diagnostics are still returned, with no reports.
+pub fn validate_path_with_options<T: Into<PathBuf>>(
+    path: T,
+    options: &ReadOptions,
+) -> Vec<(PathBuf, Vec<String>)> {
+    let mut paths = vec![];
+    let mut results = vec![];
+
+    collect_source_paths(T::into(path), &mut paths, &mut results);
+
+    for path in paths {
+        let reports = match read_data_file(&path, options.max_source_bytes) {
+            Ok(Some(source)) => {
+                let reader = Reader::new(vec![display_path(&path)], vec![source], options);
+
+                reader.read_reports(false).1
+            }
+            Ok(None) => vec![format!(
+                "{}\n",
+                source_too_large(&path, options.max_source_bytes.unwrap_or_default())
+            )],
+            Err(error) => vec![format!(
+                "Failed to read file \"{}\": {error}\n",
+                path.display()
+            )],
+        };
+
+        results.push((path, reports));
+    }
+
+    results
+}
+
+// every data file under `path`, where anything that can't be searched is reported on its own
+fn collect_source_paths(
+    path: PathBuf,
+    paths: &mut Vec<PathBuf>,
+    problems: &mut Vec<(PathBuf, Vec<String>)>,
+) {
+    if path.is_dir() {
+        match fs::read_dir(&path) {
+            Ok(dir) => {
+                for entry in dir.flatten() {
+                    collect_source_paths(entry.path(), paths, problems);
+                }
+            }
+            Err(error) => {
+                let report = format!("Failed to read directory \"{}\": {error}\n", path.display());
+
+                problems.push((path, vec![report]));
+            }
+        }
+    } else if path.is_file() {
+        if is_data_file(&path) {
+            paths.push(path);
+        }
+    } else {
+        let report = format!("File \"{}\" does not exist\n", path.display());
+
+        problems.push((path, vec![report]));
+    }
+}
+
+// whether `path` should be read as a data file, which with the `gzip` feature includes gzipped ones
+fn is_data_file(path: &Path) -> bool {
+    let has_extension = |path: &Path| matches!(path.extension(), Some(ext) if matches!(ext.to_str(), Some(ext) if ext == EXTENSION));
+
+    has_extension(path)
+        || (cfg!(feature = "gzip") && is_gzipped(path) && has_extension(&display_path(path)))
+}
+
+fn is_gzipped(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if matches!(ext.to_str(), Some(ext) if ext == GZIP_EXTENSION))
+}
+
+// the path a source is named by in reports, which leaves out any `.gz`
+fn display_path(path: &Path) -> PathBuf {
+    if cfg!(feature = "gzip") && is_gzipped(path) {
+        path.with_extension("")
+    } else {
+        path.to_owned()
+    }
+}
+
+// reads a data file, or `None` if it's larger than `max_source_bytes` once decompressed
+fn read_data_file(path: &Path, max_source_bytes: Option<usize>) -> io::Result<Option<String>> {
+    let file = fs::File::open(path)?;
+
+    #[cfg(feature = "gzip")]
+    if is_gzipped(path) {
+        return read_limited(flate2::read::GzDecoder::new(file), max_source_bytes);
+    }
+
+    read_limited(file, max_source_bytes)
+}
+
+// reads `input` to the end, or `None` as soon as it's longer than `max_source_bytes`
+fn read_limited(input: impl Read, max_source_bytes: Option<usize>) -> io::Result<Option<String>> {
+    let mut bytes = vec![];
+
+    // reading one byte past the limit is enough to know the source is too large
+    let limit = max_source_bytes
+        .map(|max_source_bytes| {
+            u64::try_from(max_source_bytes)
+                .unwrap_or(u64::MAX)
+                .saturating_add(1)
+        })
+        .unwrap_or(u64::MAX);
+
+    input.take(limit).read_to_end(&mut bytes)?;
+
+    // the limit can cut a character in half, so the length is checked before decoding
+    if max_source_bytes.is_some_and(|max_source_bytes| bytes.len() > max_source_bytes) {
+        return Ok(None);
+    }
+
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// applies any changes the options make to a source before it's parsed
+fn prepare_source(source: String, options: &ReadOptions) -> String {
+    if options.normalize_newlines && source.contains('\r') {
+        source.replace("\r\n", "\n").replace('\r', "\n")
+    } else {
+        source
+    }
+}
+
+fn source_too_large(path: &Path, max_source_bytes: usize) -> String {
+    format!(
+        "File \"{}\" is larger than the maximum of {max_source_bytes} bytes",
+        path.display()
+    )
+}
+
 enum ReadResult {
     Ok,
     Err,
@@ -71,6 +404,7 @@ fn read_source(
     file_path: PathBuf,
     paths: &mut Vec<PathBuf>,
     sources: &mut Vec<String>,
+    options: &ReadOptions,
 ) -> ReadResult {
     if !file_path.exists() {
         eprintln!("File \"{}\" does not exist", file_path.display());
@@ -82,7 +416,10 @@ fn read_source(
             for entry in dir.flatten() {
                 let file_path = entry.path();
 
-                all_success &= matches!(read_source(file_path, paths, sources), ReadResult::Ok);
+                all_success &= matches!(
+                    read_source(file_path, paths, sources, options),
+                    ReadResult::Ok
+                );
             }
 
             if all_success {
@@ -95,11 +432,26 @@ fn read_source(
             ReadResult::Err
         }
     } else if file_path.is_file() {
-        if matches!(file_path.extension(), Some(ext) if matches!(ext.to_str(), Some(ext) if ext == EXTENSION))
-        {
-            match fs::read_to_string(&file_path) {
-                Ok(source) => {
-                    paths.push(file_path);
+        if is_data_file(&file_path) {
+            if let Some(max_source_bytes) = options.max_source_bytes
+                && let Ok(metadata) = fs::metadata(&file_path)
+                && usize::try_from(metadata.len()).unwrap_or(usize::MAX) > max_source_bytes
+            {
+                eprintln!("{}", source_too_large(&file_path, max_source_bytes));
+
+                return ReadResult::Err;
+            }
+
+            match read_data_file(&file_path, options.max_source_bytes) {
+                Ok(None) => {
+                    let max_source_bytes = options.max_source_bytes.unwrap_or_default();
+
+                    eprintln!("{}", source_too_large(&file_path, max_source_bytes));
+
+                    ReadResult::Err
+                }
+                Ok(Some(source)) => {
+                    paths.push(display_path(&file_path));
                     sources.push(source);
 
                     ReadResult::Ok
@@ -126,6 +478,7 @@ fn read_source(
 
 pub struct DataFolder {
     paths: HashMap<SourceIndex, PathBuf>,
+    source_indices: HashMap<PathBuf, SourceIndex>,
     data: Data,
 }
 
@@ -134,6 +487,16 @@ impl DataFolder {
         self.paths.get(&source_index)
     }
 
+    /// The path every source was read from, such as for `render_all`
+    pub fn paths(&self) -> &HashMap<SourceIndex, PathBuf> {
+        &self.paths
+    }
+
+    /// The source that was read from `path`, which must be given the same way it was read
+    pub fn source_index_of(&self, path: &Path) -> Option<SourceIndex> {
+        self.source_indices.get(path).copied()
+    }
+
     pub fn data(&self) -> &Data {
         &self.data
     }
@@ -143,70 +506,429 @@ struct Reader {
     paths: Vec<PathBuf>,
     sources: Vec<SourceIndex>,
     data: Data,
+    rejected: Vec<String>,
+    options: ReadOptions,
 }
 
 impl Reader {
-    fn new(paths: Vec<PathBuf>, sources: Vec<String>) -> Self {
-        let mut data = Data::default();
-
-        let sources = sources
-            .into_iter()
-            .map(|source| data.insert_source(source))
-            .collect::<Vec<_>>();
+    fn new(paths: Vec<PathBuf>, sources: Vec<String>, options: &ReadOptions) -> Self {
+        let mut data =
+            Data::with_estimated_size(sources.iter().map(String::len).sum(), sources.len());
+
+        let mut kept_paths = vec![];
+        let mut kept_sources = vec![];
+        let mut rejected = vec![];
+
+        for (path, source) in paths.into_iter().zip(sources) {
+            if let Some(max_source_bytes) = options.max_source_bytes
+                && source.len() > max_source_bytes
+            {
+                rejected.push(format!("{}\n", source_too_large(&path, max_source_bytes)));
+            } else {
+                kept_paths.push(path);
+                kept_sources.push(data.insert_source(prepare_source(source, options)));
+            }
+        }
 
         Reader {
-            paths,
-            sources,
+            paths: kept_paths,
+            sources: kept_sources,
             data,
+            rejected,
+            options: options.clone(),
         }
     }
 
-    fn read<T: Write>(mut self, output: &mut T, colored_errors: bool) -> io::Result<DataFolder> {
-        let mut reports = vec![];
+    fn read<T: Write>(self, output: &mut T, colored_errors: bool) -> io::Result<DataFolder> {
+        let (data_folder, errors) = self.read_reports(colored_errors);
+
+        for error in errors {
+            write!(output, "{}", error)?;
+        }
+
+        Ok(data_folder)
+    }
+
+    fn read_reports(self, colored_errors: bool) -> (DataFolder, Vec<String>) {
+        let (data_folder, reports, _diagnostics) = self.read_all(colored_errors, false);
+
+        (data_folder, reports)
+    }
+
+    // like `read_reports`, but with parse errors resolved into diagnostics instead of rendered
+    // into reports
+    fn read_diagnostics(self) -> (DataFolder, Vec<String>, Vec<ResolvedDiagnostic>) {
+        self.read_all(false, true)
+    }
+
+    fn read_all(
+        mut self,
+        colored_errors: bool,
+        resolve_diagnostics: bool,
+    ) -> (DataFolder, Vec<String>, Vec<ResolvedDiagnostic>) {
+        let mut reports = mem::take(&mut self.rejected);
+        let mut diagnostics = vec![];
+
+        let mut read_paths = self
+            .paths
+            .iter()
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .collect::<HashSet<_>>();
 
-        for (i, &source_index) in self.sources.iter().enumerate() {
+        // the roots of each source, which are only pushed once every include has been read
+        let mut parsed_roots = vec![];
+        // the source each include node read, or `None` if it didn't read one
+        let mut includes = HashMap::new();
+
+        let top_level_sources = self.sources.len();
+
+        let mut i = 0;
+
+        while let Some(&source_index) = self.sources.get(i) {
             let mut parser = Parser::new(source_index);
 
-            parser.parse(&mut self.data);
+            let roots = parser.parse_roots(&mut self.data);
+
+            if self.options.follow_includes {
+                self.read_includes(i, &roots, &mut includes, &mut read_paths, &mut reports);
+            }
+
+            parsed_roots.push(roots);
+
+            let mut errors = parser.take_errors();
 
-            let errors = parser.take_errors();
+            let mut omitted = 0;
 
-            if !errors.is_empty() {
+            if let Some(max_errors) = self.options.max_errors_per_source
+                && errors.len() > max_errors
+            {
+                omitted = errors.len() - max_errors;
+                errors.truncate(max_errors);
+            }
+
+            if resolve_diagnostics {
+                let source = self.data.get_source(source_index).unwrap();
+                let path = self.paths[i].display().to_string();
+
+                diagnostics.extend(
+                    errors
+                        .into_iter()
+                        .map(|error| Diagnostic::from(error).resolve(source, &path)),
+                );
+            } else if !errors.is_empty() {
                 let mut report_data = ReportData::new(
                     self.data.get_source(source_index).unwrap().to_owned(),
                     "ERROR",
                     self.paths.get(i).unwrap().display(),
                     "[snip]",
-                    if colored_errors {
-                        ReportColors::default()
-                    } else {
-                        ReportColors::colorless()
-                    },
+                    ReportColors::for_output(colored_errors),
                 );
 
+                if let Some(divider_width) = self.options.divider_width {
+                    report_data.divider_width = divider_width;
+                }
+
+                if let Some(context_lines) = self.options.context_lines {
+                    report_data.context_lines = context_lines;
+                }
+
+                report_data.notes_position = self.options.notes_position;
+                report_data.header_format = self.options.header_format;
+                report_data.group_errors = self.options.group_errors;
+
                 for error in errors {
                     error.report(&mut report_data);
                 }
 
-                reports.push(report_data);
+                if self.options.group_errors {
+                    reports.extend(report_data.take_grouped_errors());
+                } else {
+                    reports.append(&mut report_data.take_errors());
+                }
             }
+
+            if omitted > 0 {
+                reports.push(format!(
+                    "... and {omitted} more {} in \"{}\"\n",
+                    if omitted == 1 { "error" } else { "errors" },
+                    self.paths[i].display()
+                ));
+            }
+
+            i += 1;
+        }
+
+        for i in 0..top_level_sources {
+            self.push_roots(i, &parsed_roots, &includes);
         }
 
-        if !reports.is_empty() {
-            for report_data in reports.iter_mut() {
-                for error in report_data.take_errors() {
-                    write!(output, "{}", error)?;
+        let paths = self
+            .sources
+            .into_iter()
+            .zip(self.paths)
+            .collect::<HashMap<_, _>>();
+
+        let source_indices = paths
+            .iter()
+            .map(|(&source_index, path)| (path.clone(), source_index))
+            .collect::<HashMap<_, _>>();
+
+        (
+            DataFolder {
+                paths,
+                source_indices,
+                data: self.data,
+            },
+            reports,
+            diagnostics,
+        )
+    }
+
+    // pushes the roots of the `i`th source, with the roots of each included source in place of
+    // the include node that read it
+    fn push_roots(
+        &mut self,
+        i: usize,
+        parsed_roots: &[Vec<NodeIndex>],
+        includes: &HashMap<NodeIndex, Option<usize>>,
+    ) {
+        for &root in &parsed_roots[i] {
+            match includes.get(&root) {
+                Some(included) => {
+                    if let Some(included) = *included {
+                        self.push_roots(included, parsed_roots, includes);
+                    }
+
+                    self.data.remove_node(root);
                 }
+                None => self.data.push_root_node(self.sources[i], root),
             }
         }
+    }
 
-        Ok(DataFolder {
-            paths: self
-                .sources
-                .into_iter()
-                .zip(self.paths)
-                .collect::<HashMap<_, _>>(),
-            data: self.data,
-        })
+    fn read_includes(
+        &mut self,
+        i: usize,
+        roots: &[NodeIndex],
+        includes: &mut HashMap<NodeIndex, Option<usize>>,
+        read_paths: &mut HashSet<PathBuf>,
+        reports: &mut Vec<String>,
+    ) {
+        let source_index = self.sources[i];
+        let directory = self.paths[i].parent().unwrap_or(Path::new("")).to_owned();
+
+        let included = roots
+            .iter()
+            .filter_map(|&root| match self.data.get_tokens(root)? {
+                [include, path, ..]
+                    if self.data.get_lexeme(source_index, *include) == Some("include") =>
+                {
+                    self.data
+                        .get_lexeme(source_index, *path)
+                        .map(|path| (root, directory.join(path)))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        for (root, path) in included {
+            includes.insert(root, None);
+
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+
+            if !read_paths.insert(canonical) {
+                continue;
+            }
+
+            match read_data_file(&path, self.options.max_source_bytes) {
+                Ok(Some(source)) => {
+                    let source = prepare_source(source, &self.options);
+
+                    includes.insert(root, Some(self.sources.len()));
+                    self.sources.push(self.data.insert_source(source));
+                    self.paths.push(display_path(&path));
+                }
+                Ok(None) => {
+                    let max_source_bytes = self.options.max_source_bytes.unwrap_or_default();
+
+                    reports.push(format!("{}\n", source_too_large(&path, max_source_bytes)));
+                }
+                Err(error) => {
+                    reports.push(format!(
+                        "Failed to read included file \"{}\": {error}\n",
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_reader_parses_a_cursor() {
+        let (data_folder, reports) =
+            read_reader(io::Cursor::new(b"ship A\n\tcost 10\n"), "ships.txt").unwrap();
+
+        let data = data_folder.data();
+        let source_index = data_folder.source_index_of(Path::new("ships.txt")).unwrap();
+
+        assert!(reports.is_empty());
+        assert_eq!(data.root_nodes().len(), 1);
+        assert_eq!(
+            data.key_of(source_index, data.root_nodes()[0].1),
+            Some("ship")
+        );
+    }
+
+    #[test]
+    fn read_reader_rejects_a_source_over_the_limit_mid_character() {
+        let options = ReadOptions {
+            max_source_bytes: Some(2),
+            ..ReadOptions::default()
+        };
+
+        let (data_folder, reports) =
+            read_reader_with_options(io::Cursor::new("ab\u{e9}cd"), "long.txt", &options).unwrap();
+
+        assert_eq!(
+            reports,
+            [format!("{}\n", source_too_large(Path::new("long.txt"), 2))]
+        );
+        assert!(data_folder.data().root_nodes().is_empty());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzipped_files_stop_decompressing_past_the_limit() {
+        use std::io::Write as _;
+
+        let directory = std::env::temp_dir().join("endless_sky_rw_gzip_limit");
+        let path = directory.join("big.txt.gz");
+
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut encoder =
+            flate2::write::GzEncoder::new(fs::File::create(&path).unwrap(), Default::default());
+        encoder
+            .write_all("thing \u{e9}\n".repeat(100_000).as_bytes())
+            .unwrap();
+        encoder.finish().unwrap();
+
+        assert!(read_data_file(&path, Some(100)).unwrap().is_none());
+        assert!(read_data_file(&path, None).unwrap().is_some());
+
+        let options = ReadOptions {
+            max_source_bytes: Some(100),
+            ..ReadOptions::default()
+        };
+
+        let results = validate_path_with_options(&path, &options);
+
+        assert_eq!(
+            results[0].1,
+            [format!("{}\n", source_too_large(&path, 100))]
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn read_bytes_reads_embedded_ascii_without_diagnostics() {
+        let (data_folder, reports) = read_bytes(b"ship A\n\tcost 10\n", "ships.txt");
+
+        assert!(reports.is_empty());
+        assert_eq!(data_folder.data().root_nodes().len(), 1);
+    }
+
+    #[test]
+    fn read_bytes_reports_the_first_non_ascii_byte() {
+        let (_data_folder, reports) = read_bytes("ship \u{e9}\n".as_bytes(), "ships.txt");
+
+        assert_eq!(
+            reports[0],
+            "File \"ships.txt\" is not ASCII, starting at byte 5\n"
+        );
+
+        let (_data_folder, reports) = read_bytes(b"ship \xff\n", "ships.txt");
+
+        assert_eq!(
+            reports[..2],
+            [
+                "File \"ships.txt\" is not ASCII, starting at byte 5\n",
+                "File \"ships.txt\" is not valid UTF-8, so its invalid bytes were replaced\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn includes_are_spliced_in_place_of_the_include_node() {
+        let directory = std::env::temp_dir().join("endless_sky_rw_includes");
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("master.txt"), "a\ninclude child.txt\nb\n").unwrap();
+        fs::write(directory.join("child.txt"), "c\ninclude master.txt\nd\n").unwrap();
+
+        let options = ReadOptions {
+            follow_includes: true,
+            ..ReadOptions::default()
+        };
+
+        let data_folder = read_path_with_options(directory.join("master.txt"), &options).unwrap();
+        let data = data_folder.data();
+
+        let keys = data
+            .root_nodes()
+            .iter()
+            .filter_map(|&(source_index, root)| data.key_of(source_index, root))
+            .collect::<Vec<_>>();
+
+        assert_eq!(keys, ["a", "c", "d", "b"]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn try_parse_str_fails_on_deep_nesting_instead_of_overflowing() {
+        let source = (0..10_000)
+            .map(|depth| format!("{}a\n", "\t".repeat(depth)))
+            .collect::<String>();
+
+        assert!(matches!(
+            try_parse_str(&source),
+            Err(ParseFailure::NestingTooDeep { max_depth, .. }) if max_depth == MAX_UNTRUSTED_DEPTH
+        ));
+    }
+
+    #[test]
+    fn try_parse_str_handles_huge_tokens() {
+        let source = format!("a {}\n\"{}", "b".repeat(1 << 20), "c".repeat(1 << 20));
+
+        let (data, errors) = try_parse_str(&source).unwrap();
+
+        assert_eq!(data.root_nodes().len(), 2);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn reports_with_spans_made_through_the_api_do_not_panic() {
+        let source = "a \u{e9}\n";
+
+        let mut report_data = ReportData::new(
+            source,
+            "ERROR",
+            "test.txt",
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        for span in [Span::new(8, 2), Span::new(3, 100), Span::new(4, 4)] {
+            reporting::quick(span, String::new, Vec::new).report(&mut report_data);
+        }
+
+        assert_eq!(report_data.take_errors().len(), 3);
     }
 }
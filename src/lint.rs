@@ -0,0 +1,61 @@
+use crate::reporting::{Reportable, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    OrphanIndentation,
+    SuspiciousNumber { suggestion: String },
+    MissingValue { key: String },
+    DeepNesting { depth: usize, max_depth: usize },
+    InconsistentIndentation { unit: usize },
+}
+
+/// Lints are warnings rather than errors, and should be reported with a `ReportData` whose kind
+/// is `"WARNING"`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Lint {
+    kind: LintKind,
+    span: Span,
+}
+
+impl Lint {
+    pub fn new(kind: LintKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn kind(&self) -> &LintKind {
+        &self.kind
+    }
+}
+
+impl Reportable<String, String> for Lint {
+    fn span(&self) -> Span {
+        self.span
+    }
+
+    fn message(&self) -> Option<String> {
+        Some(
+            match self.kind {
+                LintKind::OrphanIndentation => "This block is indented under a line with no key",
+                LintKind::SuspiciousNumber { .. } => {
+                    "This value looks like a number, but is not one"
+                }
+                LintKind::MissingValue { .. } => "This key is missing its value",
+                LintKind::DeepNesting { .. } => "This line is nested very deeply",
+                LintKind::InconsistentIndentation { .. } => {
+                    "This line is indented inconsistently with the rest of the file"
+                }
+            }
+            .to_owned(),
+        )
+    }
+
+    fn notes(&self) -> Vec<String> {
+        match &self.kind {
+            LintKind::OrphanIndentation => vec!["Indented lines belong to the line above them, so that line needs at least one token".to_owned()],
+            LintKind::SuspiciousNumber { suggestion } => vec![format!("Did you mean `{suggestion}`?")],
+            LintKind::MissingValue { key } => vec![format!("`{key}` needs at least one value after it")],
+            LintKind::DeepNesting { depth, max_depth } => vec![format!("It's {depth} levels deep, more than the {max_depth} expected, which may mean it's indented too far")],
+            LintKind::InconsistentIndentation { unit } => vec![format!("The first indented line used {unit} spaces, so every indentation should be a multiple of that")],
+        }
+    }
+}
@@ -1,22 +1,36 @@
 pub mod error;
 
-use self::error::{ParseError, ParseErrorKind};
+use self::error::{ParseError, ParseErrorKind, ParseFailure};
 
 use crate::data::{Data, Node, NodeIndex, SourceIndex};
 
 use crate::lex::{
-    Lexer,
-    token::{Token, TokenKind},
+    IndentKind, Lexer,
+    token::{SymbolClass, Token, TokenKind},
 };
 
-use crate::reporting::Reportable;
+use crate::reporting::{self, Reportable, Span};
 
 use std::mem;
 
+type TokenHook = Box<dyn FnMut(&mut Token, &mut Data)>;
+
 pub struct Parser {
     lexer: Lexer,
     errors: Vec<ParseError>,
     indentation: usize,
+    token_hook: Option<TokenHook>,
+    record_blank_lines: bool,
+    skip_empty_nodes: Option<bool>,
+    pending_blank_lines: usize,
+    rest_of_line_keys: Vec<String>,
+    coalesce_errors: bool,
+    start_offset: usize,
+    first_token_start: Option<usize>,
+    last_token_end: Option<usize>,
+    max_depth: Option<usize>,
+    depth: usize,
+    failure: Option<ParseFailure>,
 }
 
 impl Parser {
@@ -25,31 +39,225 @@ impl Parser {
             lexer: Lexer::new(source_index),
             errors: vec![],
             indentation: 0,
+            token_hook: None,
+            record_blank_lines: false,
+            skip_empty_nodes: None,
+            pending_blank_lines: 0,
+            rest_of_line_keys: vec![],
+            coalesce_errors: false,
+            start_offset: 0,
+            first_token_start: None,
+            last_token_end: None,
+            max_depth: None,
+            depth: 0,
+            failure: None,
+        }
+    }
+
+    /// Starts parsing at `start_offset`, which should be at the start of a top-level line
+    pub fn new_at(source_index: SourceIndex, start_offset: usize) -> Self {
+        Self {
+            lexer: Lexer::new_at(source_index, start_offset),
+            start_offset,
+            ..Self::new(source_index)
+        }
+    }
+
+    /// Stops parsing at `end`, even if the source continues past it
+    pub(crate) fn stop_at(mut self, end: usize) -> Self {
+        self.lexer.stop_at(end);
+        self
+    }
+
+    /// Parses as if a token ending at `end` came before the start, so the blank lines and trivia
+    /// of the first root are counted from there
+    pub(crate) fn continue_after(mut self, end: Option<usize>) -> Self {
+        if let Some(end) = end {
+            self.lexer.trivia_from(end);
         }
+
+        self.last_token_end = end;
+        self
+    }
+
+    /// Passed through to `Lexer::line_continuation`
+    pub fn line_continuation(mut self, line_continuation: bool) -> Self {
+        self.lexer = self.lexer.line_continuation(line_continuation);
+        self
+    }
+
+    /// Passed through to `Lexer::lenient_indentation`
+    pub fn lenient_indentation(mut self, lenient_indentation: bool) -> Self {
+        self.lexer = self.lexer.lenient_indentation(lenient_indentation);
+        self
+    }
+
+    /// Passed through to `Lexer::warn_leading_zeros`
+    pub fn warn_leading_zeros(mut self, warn_leading_zeros: bool) -> Self {
+        self.lexer = self.lexer.warn_leading_zeros(warn_leading_zeros);
+        self
+    }
+
+    /// Passed through to `Lexer::capture_trivia`, and also records the trivia after the last
+    /// symbol, which can be read back with `Data::trailing_trivia`
+    pub fn capture_trivia(mut self, capture_trivia: bool) -> Self {
+        self.lexer = self.lexer.capture_trivia(capture_trivia);
+        self
+    }
+
+    /// Records how many blank lines come before each root node, which can be read back with
+    /// `Data::blank_lines_before`
+    ///
+    /// Lines with only a comment aren't counted as blank
+    pub fn record_blank_lines(mut self, record_blank_lines: bool) -> Self {
+        self.record_blank_lines = record_blank_lines;
+        self
+    }
+
+    /// Decides what happens to lines that are indented but have no symbols
+    ///
+    /// By default they're skipped like blank lines, though a root node with no tokens is still
+    /// produced when what's parsed has no symbols at all. `true` drops that root node as well.
+    /// `false` drops it too, but keeps each indented line without symbols as a node with no
+    /// tokens, among the siblings of the next line with symbols, or as a root node if there's none
+    pub fn skip_empty_nodes(mut self, skip_empty_nodes: bool) -> Self {
+        self.skip_empty_nodes = Some(skip_empty_nodes);
+        self
+    }
+
+    /// Joins everything after any of `keys` on its line into a single quoted value, spanning from
+    /// the start of the first value to the end of the last, so `description foo bar` has the
+    /// tokens `description` and `foo bar`
+    ///
+    /// A quoted first or last value keeps its quotes, so `description "foo" bar` joins into
+    /// `"foo" bar`
+    pub fn rest_of_line_keys(mut self, keys: &[&str]) -> Self {
+        self.rest_of_line_keys = keys.iter().map(|&key| key.to_owned()).collect();
+        self
+    }
+
+    /// Merges each error into the one before it when they're of the same kind and only
+    /// whitespace separates them, so a region of garbage is reported once with a span covering
+    /// all of it
+    ///
+    /// The merged error keeps the notes and labels of the first error in the run. The parser
+    /// doesn't put `Error` nodes in the tree, so only the errors returned by `take_errors` are
+    /// merged, and the nodes parsed are the same either way.
+    pub fn coalesce_errors(mut self, coalesce_errors: bool) -> Self {
+        self.coalesce_errors = coalesce_errors;
+        self
+    }
+
+    /// Stops parsing at the first node nested more than `max_depth` levels below a root node,
+    /// which `take_failure` then returns
+    ///
+    /// Nodes are parsed recursively, so this keeps deeply nested input from overflowing the stack
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Calls `hook` on every token before it's pushed into a node
+    ///
+    /// The hook may push onto the source being parsed to retarget a token's span, since parsing
+    /// stops wherever the source ended when `parse` was called
+    pub fn with_token_hook(mut self, hook: impl FnMut(&mut Token, &mut Data) + 'static) -> Self {
+        self.token_hook = Some(Box::new(hook));
+        self
     }
 
     fn source_index(&self) -> SourceIndex {
         self.lexer.source_index()
     }
 
-    fn error(&mut self, error: ParseError) {
+    pub fn detected_indent(&self) -> IndentKind {
+        self.lexer.detected_indent()
+    }
+
+    fn error(&mut self, data: &Data, error: ParseError) {
+        let source = data.get_source(self.source_index());
+
+        if self.coalesce_errors
+            && let Some(previous) = self.errors.last_mut()
+            && previous.kind().is_same_kind_as(&error.kind())
+            && let Some(between) = source.and_then(|source| {
+                source.get(previous.span().end_as_usize()..error.span().start_as_usize())
+            })
+            && between.chars().all(|ch| ch.is_ascii_whitespace())
+        {
+            *previous =
+                ParseError::new(previous.kind(), previous.span().combine_with(&error.span()));
+            return;
+        }
+
         self.errors.push(error);
     }
 
     pub fn take_errors(&mut self) -> Vec<ParseError> {
         mem::take(&mut self.errors)
     }
+
+    pub fn take_failure(&mut self) -> Option<ParseFailure> {
+        self.failure.take()
+    }
 }
 
 impl Parser {
     pub fn parse(&mut self, data: &mut Data) {
-        while self.peek(data).is_some() {
-            let node = self.node(data);
+        for node in self.parse_roots(data) {
             data.push_root_node(self.source_index(), node);
         }
     }
 
-    fn node(&mut self, data: &mut Data) -> NodeIndex {
+    /// Like `parse`, but returns the root nodes instead of pushing them
+    pub(crate) fn parse_roots(&mut self, data: &mut Data) -> Vec<NodeIndex> {
+        if let Some(source) = data.get_source(self.source_index()) {
+            self.lexer.stop_at(source.len());
+        }
+
+        let mut roots = vec![];
+
+        while self.failure.is_none() && self.peek(data).is_some() {
+            let previous_end = self.last_token_end;
+
+            self.first_token_start = None;
+
+            self.indentation(data);
+            roots.extend(self.blank_line_nodes(data));
+
+            let Some(root) = self.node(data) else {
+                continue;
+            };
+
+            if self.record_blank_lines
+                && let Some(start) = self.first_token_start
+            {
+                let blank_lines = self.blank_lines_between(data, previous_end, start);
+
+                data.set_blank_lines_before(root, blank_lines);
+            }
+
+            roots.push(root);
+        }
+
+        roots.extend(self.blank_line_nodes(data));
+
+        if self.failure.is_none()
+            && let Some(trivia) = self.lexer.trailing_trivia(data)
+        {
+            data.set_trailing_trivia(self.source_index(), trivia);
+        }
+
+        roots
+    }
+
+    fn blank_lines_between(&self, data: &Data, previous_end: Option<usize>, start: usize) -> usize {
+        data.get_source(self.source_index()).map_or(0, |source| {
+            blank_lines_between(source, previous_end, self.start_offset, start)
+        })
+    }
+
+    fn node(&mut self, data: &mut Data) -> Option<NodeIndex> {
         self.indentation(data);
 
         let current_indentation = self.indentation;
@@ -59,27 +267,108 @@ impl Parser {
         while let Some(token) = self.peek(data)
             && let TokenKind::Symbol = token.kind()
         {
-            tokens.push(self.advance(data).unwrap());
+            let mut token = self.advance(data).unwrap();
+
+            self.first_token_start
+                .get_or_insert(token.span().start_as_usize());
+            self.last_token_end = Some(token.span().end_as_usize());
+
+            if let Some(hook) = self.token_hook.as_mut() {
+                hook(&mut token, data);
+            }
+
+            tokens.push(token);
         }
 
+        self.join_rest_of_line(data, &mut tokens);
+
         let mut children = vec![];
 
         self.indentation(data);
 
-        while self.peek(data).is_some() && self.indentation > current_indentation {
+        while self.failure.is_none()
+            && self.peek(data).is_some()
+            && self.indentation > current_indentation
+        {
+            if let Some(max_depth) = self.max_depth
+                && self.depth >= max_depth
+            {
+                let span = self
+                    .peek(data)
+                    .map_or(Span::new(0, 0), |token| token.span());
+
+                self.failure = Some(ParseFailure::NestingTooDeep { max_depth, span });
+                break;
+            }
+
+            children.extend(self.blank_line_nodes(data));
+
+            self.depth += 1;
             let node = self.node(data);
-            children.push(node);
+            self.depth -= 1;
+
+            children.extend(node);
             self.indentation(data);
         }
 
         if !children.is_empty() {
-            data.insert_node(Node::Parent { tokens, children })
+            Some(data.insert_node(Node::Parent { tokens, children }))
+        } else if self.skip_empty_nodes.is_some() && tokens.is_empty() {
+            None
+        } else {
+            Some(data.insert_node(Node::Some { tokens }))
+        }
+    }
+
+    // an empty node for each indented line without symbols since the last call, which is kept
+    // until the parent of the next line with symbols is known
+    fn blank_line_nodes(&mut self, data: &mut Data) -> Vec<NodeIndex> {
+        (0..mem::take(&mut self.pending_blank_lines))
+            .map(|_| data.insert_node(Node::Some { tokens: vec![] }))
+            .collect()
+    }
+
+    fn join_rest_of_line(&mut self, data: &Data, tokens: &mut Vec<Token>) {
+        let [key, first, .., last] = tokens.as_slice() else {
+            return;
+        };
+
+        if !data
+            .get_lexeme(self.source_index(), *key)
+            .is_some_and(|key| self.rest_of_line_keys.iter().any(|rest| rest == key))
+        {
+            return;
+        }
+
+        // quoted values keep their quotes, so a quote at either end isn't left unmatched
+        let source = data.get_source(self.source_index()).unwrap_or_default();
+        let span = quoted_span(first, source).combine_with(&quoted_span(last, source));
+
+        // text with its quotes kept can't be quoted again, so it's written back as it is
+        let symbol_class = if span == first.span().combine_with(&last.span()) {
+            SymbolClass::Quoted
         } else {
-            data.insert_node(Node::Some { tokens })
+            SymbolClass::Verbatim
+        };
+
+        let mut value = first.with_span(span).with_symbol_class(symbol_class);
+
+        // the trivia around the value stops at its quotes, so they aren't written twice
+        if let Some(trivia) = first.leading_trivia() {
+            value = value
+                .with_leading_trivia(Span::new(trivia.start_as_usize(), span.start_as_usize()));
         }
+
+        self.lexer.trivia_from(span.end_as_usize());
+        self.last_token_end = Some(span.end_as_usize());
+
+        tokens.truncate(1);
+        tokens.push(value);
     }
 
     fn indentation(&mut self, data: &mut Data) {
+        let mut indented = false;
+
         loop {
             match self.peek(data).map(|t| t.kind()) {
                 None => return,
@@ -87,10 +376,19 @@ impl Parser {
                 Some(TokenKind::Indent) => {
                     self.advance(data);
                     self.indentation += 1;
+                    indented = true;
                 }
                 Some(TokenKind::Newline) => {
+                    if self.skip_empty_nodes == Some(false) && indented {
+                        self.pending_blank_lines += 1;
+                    }
+
                     self.advance(data);
                     self.indentation = 0;
+                    indented = false;
+                }
+                Some(TokenKind::Dedent) => {
+                    self.advance(data);
                 }
             }
         }
@@ -102,10 +400,10 @@ impl Parser {
                 unreachable!()
             };
 
-            self.error(ParseError::new(
-                ParseErrorKind::LexError(lex_error),
-                lex_error.span(),
-            ));
+            self.error(
+                data,
+                ParseError::new(ParseErrorKind::LexError(lex_error), lex_error.span()),
+            );
         }
     }
 
@@ -129,3 +427,313 @@ impl Parser {
         }
     }
 }
+
+// the blank lines after the line `previous_end` is on, or after `start_offset` if there's no
+// previous token, and before the line `start` is on
+pub(crate) fn blank_lines_between(
+    source: &str,
+    previous_end: Option<usize>,
+    start_offset: usize,
+    start: usize,
+) -> usize {
+    let region_start = match previous_end {
+        Some(previous_end) => source[previous_end..]
+            .find('\n')
+            .map_or(source.len(), |i| previous_end + i + 1),
+        None => start_offset,
+    };
+
+    let region_end = reporting::line_start(source, start);
+
+    source.get(region_start..region_end).map_or(0, |region| {
+        region.lines().filter(|line| line.trim().is_empty()).count()
+    })
+}
+
+// the span of a token along with its quotes, if it was quoted
+fn quoted_span(token: &Token, source: &str) -> Span {
+    let span = token.span();
+
+    if token.symbol_class() != Some(SymbolClass::Quoted) {
+        return span;
+    }
+
+    let (start, end) = (span.start_as_usize(), span.end_as_usize());
+
+    let Some(quote @ (b'"' | b'`')) = start.checked_sub(1).map(|i| source.as_bytes()[i]) else {
+        return span;
+    };
+
+    if source.as_bytes().get(end) == Some(&quote) {
+        Span::new(start - 1, end + 1)
+    } else {
+        Span::new(start - 1, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::WriteOptions;
+
+    // parses `source` and returns the keys of every child of the first root node
+    fn parse_children(source: &str, lenient: bool) -> (Vec<String>, Vec<ParseError>) {
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut parser = Parser::new(source_index).lenient_indentation(lenient);
+        parser.parse(&mut data);
+
+        let root = data.root_nodes()[0].1;
+        let children = data
+            .get_children(root)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|&child| data.key_of(source_index, child))
+            .map(str::to_owned)
+            .collect();
+
+        (children, parser.take_errors())
+    }
+
+    fn detected_indent(source: &str) -> IndentKind {
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut parser = Parser::new(source_index);
+        parser.parse(&mut data);
+
+        parser.detected_indent()
+    }
+
+    #[test]
+    fn detected_indent_reports_what_the_source_was_indented_with() {
+        assert_eq!(detected_indent("a\n\tb\n\t\tc\n"), IndentKind::Tab);
+        assert_eq!(detected_indent("a\n  b\n    c\n"), IndentKind::Space);
+        assert_eq!(detected_indent("a\nb\n"), IndentKind::Unknown);
+        assert_eq!(detected_indent("a\n\tb\n    c\n"), IndentKind::Mixed);
+    }
+
+    #[test]
+    fn mixed_indentation_is_an_error_by_default() {
+        let (_children, errors) = parse_children("a\n\tb\n    c\n", false);
+
+        assert!(!errors.is_empty());
+    }
+
+    #[test]
+    fn lenient_indentation_reads_each_run_as_one_level() {
+        let (children, errors) = parse_children("a\n\tb\n    c\n", true);
+
+        assert!(errors.is_empty());
+        assert_eq!(children, ["b", "c"]);
+    }
+
+    #[test]
+    fn lenient_indentation_still_nests_deeper_runs() {
+        let mut data = Data::default();
+        let source_index = data.insert_source("a\n  b\n\t\tc\n    d\n  e\nf\n".to_owned());
+
+        let mut parser = Parser::new(source_index).lenient_indentation(true);
+        parser.parse(&mut data);
+
+        assert!(parser.take_errors().is_empty());
+
+        let roots = data.root_nodes().to_vec();
+        let b = data.child_at(roots[0].1, 0).unwrap();
+
+        assert_eq!(roots.len(), 2);
+        assert_eq!(data.get_children(roots[0].1).unwrap().len(), 2);
+        assert_eq!(data.get_children(b).unwrap().len(), 2);
+        assert_eq!(data.key_of(source_index, roots[1].1), Some("f"));
+    }
+
+    // the tree of each root node, with `_` for nodes without tokens, like `a(b,_)`
+    fn tree_shapes(source: &str, skip_empty_nodes: Option<bool>) -> Vec<String> {
+        fn shape(data: &Data, source_index: SourceIndex, node: NodeIndex) -> String {
+            let key = data.key_of(source_index, node).unwrap_or("_");
+
+            match data.get_children(node) {
+                Some(children) if !children.is_empty() => {
+                    let children = children
+                        .iter()
+                        .map(|&child| shape(data, source_index, child))
+                        .collect::<Vec<_>>();
+
+                    format!("{key}({})", children.join(","))
+                }
+                _ => key.to_owned(),
+            }
+        }
+
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut parser = Parser::new(source_index);
+
+        if let Some(skip_empty_nodes) = skip_empty_nodes {
+            parser = parser.skip_empty_nodes(skip_empty_nodes);
+        }
+
+        parser.parse(&mut data);
+
+        data.root_nodes()
+            .iter()
+            .map(|&(source_index, root)| shape(&data, source_index, root))
+            .collect()
+    }
+
+    #[test]
+    fn skip_empty_nodes_decides_what_indented_blank_lines_become() {
+        let source = "a\n\tb\n\t\n\t\tc\n\t\t\n\td\n\n\t\ne\n";
+
+        assert_eq!(tree_shapes(source, None), ["a(b(c),d)", "e"]);
+        assert_eq!(tree_shapes(source, Some(true)), ["a(b(c),d)", "e"]);
+        assert_eq!(
+            tree_shapes(source, Some(false)),
+            ["a(b(_,c),_,d)", "_", "e"]
+        );
+    }
+
+    #[test]
+    fn skip_empty_nodes_decides_what_a_source_without_symbols_becomes() {
+        let source = "\t\n\n# comment\n";
+
+        assert_eq!(tree_shapes(source, None), ["_"]);
+        assert!(tree_shapes(source, Some(true)).is_empty());
+        assert_eq!(tree_shapes(source, Some(false)), ["_"]);
+    }
+
+    fn rest_of_line(source: &str) -> Vec<String> {
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut parser = Parser::new(source_index).rest_of_line_keys(&["description"]);
+        parser.parse(&mut data);
+
+        let root = data.root_nodes()[0].1;
+
+        data.get_tokens(root)
+            .unwrap()
+            .iter()
+            .filter_map(|&token| data.get_lexeme(source_index, token))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn rest_of_line_keys_join_the_values() {
+        assert_eq!(
+            rest_of_line("description foo bar  baz\n"),
+            ["description", "foo bar  baz"]
+        );
+    }
+
+    #[test]
+    fn rest_of_line_keys_keep_the_quotes_of_quoted_values() {
+        assert_eq!(
+            rest_of_line("description \"foo bar\" baz\n"),
+            ["description", "\"foo bar\" baz"]
+        );
+        assert_eq!(
+            rest_of_line("description foo `bar baz`\n"),
+            ["description", "foo `bar baz`"]
+        );
+    }
+
+    fn write_rest_of_line(source: &str, lossless: bool) -> String {
+        let mut data = Data::default();
+        let source_index = data.insert_source(source.to_owned());
+
+        let mut parser = Parser::new(source_index)
+            .rest_of_line_keys(&["description"])
+            .capture_trivia(lossless);
+        parser.parse(&mut data);
+
+        let options = WriteOptions {
+            lossless,
+            ..WriteOptions::default()
+        };
+
+        let mut output = String::new();
+        data.write_root_nodes_with_options(&mut output, data.root_nodes(), &options)
+            .unwrap();
+
+        output
+    }
+
+    #[test]
+    fn rest_of_line_values_with_quotes_write_back_as_they_were() {
+        assert_eq!(
+            write_rest_of_line("description \"Hello\" world", false).trim_end(),
+            "description \"Hello\" world"
+        );
+        assert_eq!(
+            write_rest_of_line("description say `hi \"there\"`", false).trim_end(),
+            "description say `hi \"there\"`"
+        );
+        assert_eq!(
+            write_rest_of_line("description foo bar", false).trim_end(),
+            "description \"foo bar\""
+        );
+
+        for source in [
+            "description \"Hello\" world\n",
+            "description  \"Hello\" `big world`  # comment\n\tchild\n",
+        ] {
+            assert_eq!(write_rest_of_line(source, true), source);
+            assert_eq!(
+                rest_of_line(&write_rest_of_line(source, false)),
+                rest_of_line(source)
+            );
+        }
+    }
+
+    #[test]
+    fn coalesce_errors_merges_a_garbage_region_into_one_error() {
+        let source = "ship \u{e9} \u{e9}\n\t\u{e9}\nb\n";
+
+        let parse = |coalesce: bool| {
+            let mut data = Data::default();
+            let source_index = data.insert_source(source.to_owned());
+
+            let mut parser = Parser::new(source_index).coalesce_errors(coalesce);
+            parser.parse(&mut data);
+
+            (data.root_nodes().len(), parser.take_errors())
+        };
+
+        let (roots, errors) = parse(false);
+
+        assert_eq!(roots, 2);
+        assert_eq!(errors.len(), 3);
+
+        let (coalesced_roots, coalesced) = parse(true);
+
+        assert_eq!(coalesced_roots, roots);
+        assert_eq!(coalesced.len(), 1);
+        assert_eq!(
+            coalesced[0].span(),
+            errors[0].span().combine_with(&errors[2].span())
+        );
+    }
+
+    #[test]
+    fn leading_zeros_are_reported_as_warnings() {
+        let mut data = Data::default();
+        let source_index = data.insert_source("a 007\n".to_owned());
+
+        let mut parser = Parser::new(source_index).warn_leading_zeros(true);
+        parser.parse(&mut data);
+
+        let errors = parser.take_errors();
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_warning());
+        assert_eq!(
+            crate::diagnostic::Diagnostic::from(errors[0]).kind(),
+            "WARNING"
+        );
+        assert_eq!(data.get_tokens(data.root_nodes()[0].1).unwrap().len(), 2);
+    }
+}
@@ -1,11 +1,24 @@
 use crate::lex::error::LexError;
 use crate::reporting::{Reportable, Span};
 
+use std::mem;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ParseErrorKind {
     LexError(LexError),
 }
 
+impl ParseErrorKind {
+    /// Whether both are the same kind of error, whatever details they carry
+    pub fn is_same_kind_as(&self, other: &ParseErrorKind) -> bool {
+        match (self, other) {
+            (ParseErrorKind::LexError(a), ParseErrorKind::LexError(b)) => {
+                mem::discriminant(&a.kind()) == mem::discriminant(&b.kind())
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ParseError {
     kind: ParseErrorKind,
@@ -16,6 +29,17 @@ impl ParseError {
     pub fn new(kind: ParseErrorKind, span: Span) -> Self {
         Self { kind, span }
     }
+
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// Whether this is a style warning rather than something wrong with the source
+    pub fn is_warning(&self) -> bool {
+        match self.kind {
+            ParseErrorKind::LexError(lex_error) => lex_error.kind().is_warning(),
+        }
+    }
 }
 
 impl Reportable<String, String> for ParseError {
@@ -34,4 +58,19 @@ impl Reportable<String, String> for ParseError {
             ParseErrorKind::LexError(lex_error) => lex_error.notes(),
         }
     }
+
+    fn labels(&self) -> Vec<(Span, String)> {
+        match self.kind {
+            ParseErrorKind::LexError(lex_error) => lex_error.labels(),
+        }
+    }
+}
+
+/// Why parsing an untrusted source was given up on, instead of it being parsed with errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseFailure {
+    /// The source is too long for its offsets to fit in a `Span`
+    SourceTooLarge { len: usize },
+    /// A node was nested deeper than the parser allows, starting at `span`
+    NestingTooDeep { max_depth: usize, span: Span },
 }
@@ -27,14 +27,47 @@ impl Span {
         usize::try_from(self.end).expect("Span end doesn't fit within usize")
     }
 
-    pub fn combine_with(&self, other: &Span) -> Option<Span> {
-        Some(Self {
+    pub fn combine_with(&self, other: &Span) -> Span {
+        Self {
             start: self.start.min(other.start),
             end: self.end.max(other.end),
+        }
+    }
+
+    /// The smallest span covering every span given, or `None` if there weren't any
+    pub fn merge_all<I: IntoIterator<Item = Span>>(spans: I) -> Option<Span> {
+        spans
+            .into_iter()
+            .reduce(|merged, span| merged.combine_with(&span))
+    }
+
+    /// Shrinks the span to fit within a source of `len` bytes, which may leave it empty
+    pub fn clamp_to(&self, len: usize) -> Span {
+        let len = u32::try_from(len).unwrap_or(u32::MAX);
+
+        Self {
+            start: self.start.min(len),
+            end: self.end.min(len),
+        }
+    }
+
+    /// Parses the `start..end` form that `Span` is displayed as
+    pub fn parse(s: &str) -> Option<Span> {
+        let (start, end) = s.split_once("..")?;
+
+        Some(Self {
+            start: start.parse().ok()?,
+            end: end.parse().ok()?,
         })
     }
 }
 
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
 pub trait Spannable {
     type Slice;
 
@@ -183,6 +216,43 @@ impl ReportColors {
         }
     }
 
+    /// Bright white and yellow only, for terminals or eyes where the default colors blend in
+    pub fn high_contrast() -> Self {
+        Self {
+            message: ReportColor::BrightWhite,
+            note: ReportColor::BrightYellow,
+            divider: ReportColor::BrightWhite,
+            trim: ReportColor::White,
+            highlight: ReportColor::BrightYellow,
+            underline: ReportColor::BrightWhite,
+            ..Default::default()
+        }
+    }
+
+    /// Yellow and blue instead of red and green, which stay distinct under the common forms of
+    /// colorblindness
+    pub fn colorblind_safe() -> Self {
+        Self {
+            message: ReportColor::BrightYellow,
+            note: ReportColor::BrightBlue,
+            divider: ReportColor::BrightWhite,
+            trim: ReportColor::BrightBlack,
+            highlight: ReportColor::BrightYellow,
+            underline: ReportColor::BrightBlue,
+            ..Default::default()
+        }
+    }
+
+    /// The default colors when writing to a terminal, and none otherwise, so output redirected
+    /// to a file or another program isn't full of escape codes
+    pub fn for_output(is_tty: bool) -> Self {
+        if is_tty {
+            Self::default()
+        } else {
+            Self::colorless()
+        }
+    }
+
     pub fn colorless() -> Self {
         Self {
             message: ReportColor::None,
@@ -197,6 +267,54 @@ impl ReportColors {
     }
 }
 
+/// Where the `NOTE:` lines of a report go relative to its source snippet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NotesPosition {
+    Before,
+    #[default]
+    After,
+}
+
+/// How the location, kind and message at the top of each report are laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HeaderFormat {
+    /// `name:line:column` on one line, then `KIND: message` on the next
+    #[default]
+    Split,
+    /// `KIND [name:line:column] message` on one line
+    Bracketed,
+    /// `name:line:column: KIND: message` on one line, like most compilers
+    Compiler,
+}
+
+impl HeaderFormat {
+    // the header of one report, where `name` is left out of the location of grouped reports
+    fn render(
+        self,
+        color_data: &ReportColors,
+        kind: &str,
+        name: Option<&str>,
+        (line_number, column): (usize, usize),
+        message: Option<&str>,
+    ) -> String {
+        let location = match name {
+            Some(name) => format!("{name}:{line_number}:{column}"),
+            None => format!("{line_number}:{column}"),
+        };
+
+        let message = message.map_or(String::new(), |message| format!(" {message}"));
+
+        let color = format!("{}{}", color_data.esc, color_data.message.to_ansi_escape());
+        let reset = format!("{}{}", color_data.esc, color_data.reset);
+
+        match self {
+            HeaderFormat::Split => format!("{color}{location}\n{color}{kind}:{message}{reset}\n"),
+            HeaderFormat::Bracketed => format!("{color}{kind} [{location}]{message}{reset}\n"),
+            HeaderFormat::Compiler => format!("{color}{location}: {kind}:{message}{reset}\n"),
+        }
+    }
+}
+
 pub struct ReportData<S, K, N, T>
 where
     S: Display,
@@ -209,7 +327,17 @@ where
     pub name: N,
     pub trimmed: T,
     pub color_data: ReportColors,
+    /// How many dashes the divider above each report is made of
+    pub divider_width: usize,
+    /// How many non-blank lines are printed above and below the highlighted lines
+    pub context_lines: usize,
+    pub notes_position: NotesPosition,
+    pub header_format: HeaderFormat,
+    /// Keeps reports for `take_grouped_errors` instead of `take_errors`, which should be set
+    /// before anything is reported
+    pub group_errors: bool,
     error_messages: Vec<String>,
+    grouped_messages: Vec<(usize, String)>,
 }
 
 impl<S, K, N, T> ReportData<S, K, N, T>
@@ -226,14 +354,21 @@ where
             name,
             trimmed,
             color_data,
+            divider_width: DEFAULT_DIVIDER_WIDTH,
+            context_lines: DEFAULT_CONTEXT_LINES,
+            notes_position: NotesPosition::default(),
+            header_format: HeaderFormat::default(),
+            group_errors: false,
             error_messages: vec![],
+            grouped_messages: vec![],
         }
     }
 
     pub fn has_errors(&self) -> bool {
-        !self.error_messages.is_empty()
+        !self.error_messages.is_empty() || !self.grouped_messages.is_empty()
     }
 
+    /// Every report so far, which is empty if `group_errors` is set
     pub fn errors(&self) -> &[String] {
         self.error_messages.as_slice()
     }
@@ -243,10 +378,235 @@ where
         errors.append(&mut self.error_messages);
         errors
     }
+
+    /// Takes every error as a single block under one header, ordered by where they start in the
+    /// source rather than the order they were reported in
+    ///
+    /// Only errors reported while `group_errors` was set are included
+    pub fn take_grouped_errors(&mut self) -> Option<String> {
+        if self.grouped_messages.is_empty() {
+            return None;
+        }
+
+        self.grouped_messages
+            .sort_by_key(|(span_start, _)| *span_start);
+
+        let mut buffer = format!(
+            "{0}{1}{9}{2}{3}\n{4}{5}{6}{7}{8}\n",
+            self.color_data.esc,
+            self.color_data.divider.to_ansi_escape(),
+            self.color_data.esc,
+            self.color_data.reset,
+            self.color_data.esc,
+            self.color_data.message.to_ansi_escape(),
+            printed_source_map(&self.name),
+            self.color_data.esc,
+            self.color_data.reset,
+            "-".repeat(self.divider_width),
+        );
+
+        for (_, error) in self.grouped_messages.drain(..) {
+            buffer.push_str(error.as_str());
+        }
+
+        Some(buffer)
+    }
+}
+
+/// A line-by-line diff of two rendered reports, with removed lines marked `-` and added lines
+/// marked `+`
+///
+/// Removed lines use the message color and added lines the note color
+pub fn diff_reports(expected: &str, actual: &str, color_data: ReportColors) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    // longest common subsequence lengths of every pair of suffixes
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut buffer = String::new();
+
+    let mut push_line = |marker: &str, color: ReportColor, line: &str| {
+        buffer.push_str(color_data.esc);
+        buffer.push_str(color.to_ansi_escape());
+        buffer.push_str(marker);
+        buffer.push_str(line);
+        buffer.push_str(color_data.esc);
+        buffer.push_str(color_data.reset);
+        buffer.push('\n');
+    };
+
+    let (mut i, mut j) = (0, 0);
+
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            push_line("  ", ReportColor::None, expected[i]);
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1])
+        {
+            push_line("- ", color_data.message, expected[i]);
+            i += 1;
+        } else {
+            push_line("+ ", color_data.note, actual[j]);
+            j += 1;
+        }
+    }
+
+    buffer
 }
 
 const MAX_LINE_SCAN_LENGTH: usize = 40;
 
+const DEFAULT_DIVIDER_WIDTH: usize = 15;
+
+const DEFAULT_CONTEXT_LINES: usize = 1;
+
+fn printed_source_map<S: Display>(source: S) -> String {
+    source.to_string().replace('\n', "").replace('\t', "    ")
+}
+
+pub(crate) fn printed_source_length<S: Display>(source: S) -> usize {
+    source.to_string().chars().fold(0, |accum, ch| {
+        accum
+            + match ch {
+                '\t' => 4,
+                '\n' => 0,
+                _ => 1,
+            }
+    })
+}
+
+/// The offset of the start of the line `offset` is on
+///
+/// An offset past the end of `source` or inside a character is moved back to the closest one
+/// that isn't, so this doesn't panic on spans made through the api rather than by the lexer
+pub fn line_start(source: &str, offset: usize) -> usize {
+    let offset = clamp_offset(source, offset);
+
+    source[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_i, ch)| *ch != '\n')
+        .last()
+        .map(|(i, _ch)| i)
+        .unwrap_or(offset)
+}
+
+// up to `count` of the closest non-blank lines before the line starting at `before`, as the span
+// from the start of each line to the end of its last non-whitespace character, in order
+fn previous_context_lines(source: &str, before: usize, count: usize) -> Vec<(usize, usize)> {
+    let mut lines = vec![];
+    let mut end = before;
+
+    while lines.len() < count {
+        let content_end = source[..end]
+            .char_indices()
+            .rev()
+            .find(|(_i, ch)| !ch.is_ascii_whitespace())
+            .map(|(i, ch)| i + ch.len_utf8())
+            .unwrap_or(end);
+
+        if !source[content_end..end].contains('\n') {
+            break;
+        }
+
+        let start = line_start(source, content_end);
+
+        lines.push((start, content_end));
+        end = start;
+    }
+
+    lines.reverse();
+    lines
+}
+
+// up to `count` of the closest non-blank lines after the line ending at `line_end`, as the span of
+// each whole line, in order
+fn next_context_lines(source: &str, line_end: usize, count: usize) -> Vec<(usize, usize)> {
+    let mut lines = vec![];
+    let mut start_from = line_end;
+
+    while lines.len() < count {
+        let first_content = source[start_from..]
+            .char_indices()
+            .find(|(_i, ch)| !ch.is_ascii_whitespace())
+            .map(|(i, _ch)| i + start_from)
+            .unwrap_or(start_from);
+
+        let start = line_start(source, first_content);
+
+        if start < start_from || !source[start_from..start].contains('\n') {
+            break;
+        }
+
+        let end = source[start..]
+            .find('\n')
+            .map(|i| i + start)
+            .unwrap_or(source.len());
+
+        lines.push((start, end));
+        start_from = end;
+    }
+
+    lines
+}
+
+/// The 1-based line `offset` is on, where an offset past the end of `source` is on its last line
+pub fn line_number(source: &str, offset: usize) -> usize {
+    let offset = clamp_offset(source, offset);
+
+    source
+        .char_indices()
+        .take_while(|(i, _ch)| *i < offset)
+        .filter(|(_i, ch)| *ch == '\n')
+        .count()
+        + 1
+}
+
+// the bounds of `span` clamped to `source`, in order and on char boundaries, since spans made
+// through the api rather than by the lexer could otherwise make rendering panic
+fn renderable_span(source: &str, span: Span) -> (usize, usize) {
+    let span = span.clamp_to(source.len());
+
+    let start = source.floor_char_boundary(span.start_as_usize());
+    let end = source.floor_char_boundary(span.end_as_usize());
+
+    (start.min(end), start.max(end))
+}
+
+// `offset` moved back to the end of `source` and then to a char boundary
+fn clamp_offset(source: &str, offset: usize) -> usize {
+    source.floor_char_boundary(offset.min(source.len()))
+}
+
+pub(crate) fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = clamp_offset(source, offset);
+
+    let line_number = line_number(source, offset);
+
+    let line_start = line_start(source, offset);
+
+    let column = source[line_start..]
+        .char_indices()
+        .take_while(|(i, _ch)| line_start + *i < offset)
+        .last()
+        .map(|(i, _ch)| i + 1)
+        .unwrap_or(1);
+
+    (line_number, column)
+}
+
 pub trait Reportable<Message, Notes>
 where
     Message: Display,
@@ -258,28 +618,27 @@ where
 
     fn notes(&self) -> Vec<Notes>;
 
+    fn labels(&self) -> Vec<(Span, Notes)> {
+        vec![]
+    }
+
     fn printed_source_map<S>(source: S) -> String
     where
         S: Display,
     {
-        source.to_string().replace('\n', "").replace('\t', "    ")
+        printed_source_map(source)
     }
 
     fn printed_source_length<S>(source: S) -> usize
     where
         S: Display,
     {
-        source.to_string().chars().fold(0, |accum, ch| {
-            accum
-                + match ch {
-                    '\t' => 4,
-                    '\n' => 0,
-                    _ => 1,
-                }
-        })
+        printed_source_length(source)
     }
 
-    fn report<S, K, N, T>(&self, report_data: &mut ReportData<S, K, N, T>)
+    /// Renders only the highlighted source lines and the context around them, as they appear
+    /// in `report` between the message and any notes
+    fn render_snippet<S, K, N, T>(&self, report_data: &ReportData<S, K, N, T>) -> String
     where
         S: Display,
         K: Display,
@@ -287,37 +646,15 @@ where
         T: Display,
     {
         let source = report_data.source.to_string();
-        let kind = Self::printed_source_map(report_data.kind.to_string());
-        let name = Self::printed_source_map(report_data.name.to_string());
         let trimmed = Self::printed_source_map(report_data.trimmed.to_string());
 
-        let kind = kind.as_str();
         let trimmed = trimmed.as_str();
 
-        let span_start = self.span().start_as_usize();
-        let span_end = self.span().end_as_usize();
+        let (span_start, span_end) = renderable_span(&source, self.span());
 
-        let line_number = source
-            .char_indices()
-            .take_while(|(i, _ch)| *i < span_start)
-            .filter(|(_i, ch)| *ch == '\n')
-            .count()
-            + 1;
+        let (line_number, _column) = line_and_column(&source, span_start);
 
-        let line_start = source[..span_start]
-            .char_indices()
-            .rev()
-            .take_while(|(_i, ch)| *ch != '\n')
-            .last()
-            .map(|(i, _ch)| i)
-            .unwrap_or(span_start);
-
-        let column = source[line_start..]
-            .char_indices()
-            .take_while(|(i, _ch)| line_start + *i < span_start)
-            .last()
-            .map(|(i, _ch)| i + 1)
-            .unwrap_or(1);
+        let line_start = line_start(&source, span_start);
 
         let line_prefix_is_long = line_start <= span_start
             && source[line_start..span_start].chars().count()
@@ -359,107 +696,69 @@ where
             && source[second_highlight_start..line_end].chars().count()
                 > MAX_LINE_SCAN_LENGTH + trimmed.chars().count();
 
-        let last_line_end = source[..line_start]
-            .char_indices()
-            .rev()
-            .skip_while(|(_i, ch)| ch.is_ascii_whitespace())
-            .take(1)
-            .last()
-            .map(|(i, ch)| i + ch.len_utf8())
-            .unwrap_or(line_start);
-
-        let last_line_start = source[..last_line_end]
-            .char_indices()
-            .rev()
-            .take_while(|(_i, ch)| *ch != '\n')
-            .last()
-            .map(|(i, _ch)| i)
-            .unwrap_or(last_line_end);
-
-        let last_line_number = line_number
-            .checked_sub(if last_line_start < line_start {
-                source[last_line_start..line_start]
-                    .chars()
-                    .filter(|ch| *ch == '\n')
-                    .count()
-            } else {
-                0
-            })
-            .unwrap_or(line_number);
+        let previous_lines = previous_context_lines(&source, line_start, report_data.context_lines);
+        let next_lines = next_context_lines(&source, line_end, report_data.context_lines);
 
-        let last_line_not_this_line = last_line_end <= line_start
-            && source[last_line_end..line_start]
-                .chars()
-                .any(|ch| ch == '\n');
-
-        let last_line_is_long = last_line_start <= last_line_end
-            && source[last_line_start..last_line_end].chars().count()
-                > MAX_LINE_SCAN_LENGTH + trimmed.chars().count();
-
-        let next_line_start = source[line_end..]
-            .char_indices()
-            .skip_while(|(_i, ch)| ch.is_ascii_whitespace())
-            .take(1)
-            .last()
-            .map(|(i, _ch)| i + line_end)
-            .unwrap_or(line_end);
-
-        let next_line_start = source[..next_line_start]
-            .char_indices()
-            .rev()
-            .take_while(|(_i, ch)| *ch != '\n')
+        let last_printed_line_number = next_lines
             .last()
-            .map(|(i, _ch)| i)
-            .unwrap_or(next_line_start);
+            .map(|&(next_line_start, _)| self::line_number(&source, next_line_start))
+            .unwrap_or_else(|| self::line_number(&source, self::line_start(&source, line_end)));
+
+        let line_number_digits = ((last_printed_line_number + 1)
+            .checked_ilog10()
+            .unwrap_or_default()
+            + 1) as usize;
+
+        // prints a line of context, trimmed like the lines around the highlight
+        let push_context_line =
+            |buffer: &mut String, (context_start, context_end): (usize, usize)| {
+                let false_end = source[context_start..]
+                    .char_indices()
+                    .enumerate()
+                    .take_while(|(steps, (_i, ch))| {
+                        *ch != '\n' && *steps < MAX_LINE_SCAN_LENGTH + trimmed.chars().count()
+                    })
+                    .last()
+                    .map(|(_steps, (i, ch))| context_start + i + ch.len_utf8())
+                    .unwrap_or(context_end);
 
-        let next_line_end = source[next_line_start..]
-            .char_indices()
-            .take_while(|(_i, ch)| *ch != '\n')
-            .last()
-            .map(|(i, ch)| i + ch.len_utf8() + next_line_start)
-            .unwrap_or(next_line_start);
-
-        let next_line_number = line_number
-            + source[line_start..next_line_start]
-                .chars()
-                .filter(|ch| *ch == '\n')
-                .count();
-
-        let line_number_digits =
-            ((next_line_number + 1).checked_ilog10().unwrap_or_default() + 1) as usize;
-
-        let next_line_not_this_line = line_end <= next_line_start
-            && source[line_end..next_line_start]
-                .chars()
-                .any(|ch| ch == '\n');
-
-        let next_line_is_long = next_line_start <= next_line_end
-            && source[next_line_start..next_line_end].chars().count()
-                > MAX_LINE_SCAN_LENGTH + trimmed.chars().count();
-
-        let mut buffer = format!(
-            "{0}{1}---------------{2}{3}\n{4}{5}{6}:{line_number}:{column}\n{7}{8}{9}:",
-            report_data.color_data.esc,
-            report_data.color_data.divider.to_ansi_escape(),
-            report_data.color_data.esc,
-            report_data.color_data.reset,
-            report_data.color_data.esc,
-            report_data.color_data.message.to_ansi_escape(),
-            name,
-            report_data.color_data.esc,
-            report_data.color_data.message.to_ansi_escape(),
-            kind,
-        );
+                buffer.push_str(
+                    format!(
+                        " {0}{1}{3:>2$} | {4}{5}",
+                        report_data.color_data.esc,
+                        report_data.color_data.divider.to_ansi_escape(),
+                        line_number_digits,
+                        self::line_number(&source, context_start),
+                        report_data.color_data.esc,
+                        report_data.color_data.reset,
+                    )
+                    .as_str(),
+                );
 
-        if let Some(expected) = self.message() {
-            buffer.push(' ');
-            buffer.push_str(Self::printed_source_map(expected).as_str());
-        }
+                buffer
+                    .push_str(Self::printed_source_map(&source[context_start..false_end]).as_str());
+
+                if context_start <= context_end
+                    && source[context_start..context_end].chars().count()
+                        > MAX_LINE_SCAN_LENGTH + trimmed.chars().count()
+                {
+                    buffer.push_str(
+                        format!(
+                            " {0}{1}{2}{3}{4}",
+                            report_data.color_data.esc,
+                            report_data.color_data.trim.to_ansi_escape(),
+                            trimmed,
+                            report_data.color_data.esc,
+                            report_data.color_data.reset,
+                        )
+                        .as_str(),
+                    );
+                }
 
-        buffer.push_str(report_data.color_data.esc);
-        buffer.push_str(report_data.color_data.reset);
+                buffer.push('\n');
+            };
 
-        buffer.push('\n');
+        let mut buffer = String::new();
 
         let false_start = source[..span_start]
             .char_indices()
@@ -484,47 +783,8 @@ where
             .map(|(_steps, (i, ch))| span_end + i + ch.len_utf8())
             .unwrap_or(line_end);
 
-        if last_line_not_this_line {
-            let false_end = source[last_line_start..]
-                .char_indices()
-                .enumerate()
-                .take_while(|(steps, (_i, ch))| {
-                    *ch != '\n' && *steps < MAX_LINE_SCAN_LENGTH + trimmed.chars().count()
-                })
-                .last()
-                .map(|(_steps, (i, ch))| last_line_start + i + ch.len_utf8())
-                .unwrap_or(last_line_end);
-
-            buffer.push_str(
-                format!(
-                    " {0}{1}{3:>2$} | {4}{5}",
-                    report_data.color_data.esc,
-                    report_data.color_data.divider.to_ansi_escape(),
-                    line_number_digits,
-                    last_line_number,
-                    report_data.color_data.esc,
-                    report_data.color_data.reset,
-                )
-                .as_str(),
-            );
-
-            buffer.push_str(Self::printed_source_map(&source[last_line_start..false_end]).as_str());
-
-            if last_line_is_long {
-                buffer.push_str(
-                    format!(
-                        " {0}{1}{2}{3}{4}",
-                        report_data.color_data.esc,
-                        report_data.color_data.trim.to_ansi_escape(),
-                        trimmed,
-                        report_data.color_data.esc,
-                        report_data.color_data.reset,
-                    )
-                    .as_str(),
-                );
-            }
-
-            buffer.push('\n');
+        for &context_line in previous_lines.iter() {
+            push_context_line(&mut buffer, context_line);
         }
 
         if highlight_is_long
@@ -957,61 +1217,390 @@ where
 
         buffer.push('\n');
 
-        if next_line_not_this_line {
-            let false_end = source[next_line_start..]
-                .char_indices()
-                .enumerate()
-                .take_while(|(steps, (_i, ch))| {
-                    *ch != '\n' && *steps < MAX_LINE_SCAN_LENGTH + trimmed.chars().count()
-                })
-                .last()
-                .map(|(_steps, (i, ch))| next_line_start + i + ch.len_utf8())
-                .unwrap_or(next_line_end);
+        for &context_line in next_lines.iter() {
+            push_context_line(&mut buffer, context_line);
+        }
 
-            buffer.push_str(
-                format!(
-                    " {0}{1}{3:>2$} | {4}{5}",
-                    report_data.color_data.esc,
-                    report_data.color_data.divider.to_ansi_escape(),
-                    line_number_digits,
-                    next_line_number,
-                    report_data.color_data.esc,
-                    report_data.color_data.reset,
-                )
-                .as_str(),
-            );
+        buffer
+    }
 
-            buffer.push_str(Self::printed_source_map(&source[next_line_start..false_end]).as_str());
+    fn report<S, K, N, T>(&self, report_data: &mut ReportData<S, K, N, T>)
+    where
+        S: Display,
+        K: Display,
+        N: Display,
+        T: Display,
+    {
+        let source = report_data.source.to_string();
+        let kind = Self::printed_source_map(report_data.kind.to_string());
+        let name = Self::printed_source_map(report_data.name.to_string());
 
-            if next_line_is_long {
-                buffer.push_str(
-                    format!(
-                        " {0}{1}{2}{3}{4}",
-                        report_data.color_data.esc,
-                        report_data.color_data.trim.to_ansi_escape(),
-                        trimmed,
-                        report_data.color_data.esc,
-                        report_data.color_data.reset,
-                    )
-                    .as_str(),
-                );
-            }
+        let kind = kind.as_str();
 
-            buffer.push('\n');
-        }
+        let (span_start, _span_end) = renderable_span(&source, self.span());
+
+        let (line_number, column) = line_and_column(&source, span_start);
+
+        let message = self.message().map(Self::printed_source_map);
+
+        let mut buffer = self.render_snippet(report_data);
+
+        let mut notes = String::new();
 
         for note in self.notes().iter() {
-            buffer.push_str(report_data.color_data.esc);
-            buffer.push_str(report_data.color_data.note.to_ansi_escape());
+            notes.push_str(report_data.color_data.esc);
+            notes.push_str(report_data.color_data.note.to_ansi_escape());
+
+            notes.push_str("NOTE: ");
+            notes.push_str(note.to_string().as_str());
+
+            notes.push_str(report_data.color_data.esc);
+            notes.push_str(report_data.color_data.reset);
+            notes.push('\n');
+        }
+
+        for (span, label) in self.labels().iter() {
+            let (label_start, _label_end) = renderable_span(&source, *span);
+            let (line_number, column) = line_and_column(&source, label_start);
+
+            notes.push_str(report_data.color_data.esc);
+            notes.push_str(report_data.color_data.note.to_ansi_escape());
+
+            notes.push_str("NOTE: ");
+            notes.push_str(label.to_string().as_str());
+            notes.push_str(format!(" ({name}:{line_number}:{column})").as_str());
+
+            notes.push_str(report_data.color_data.esc);
+            notes.push_str(report_data.color_data.reset);
+            notes.push('\n');
+        }
+
+        match report_data.notes_position {
+            NotesPosition::Before => buffer.insert_str(0, notes.as_str()),
+            NotesPosition::After => buffer.push_str(notes.as_str()),
+        }
 
-            buffer.push_str("NOTE: ");
-            buffer.push_str(note.to_string().as_str());
+        let header = |name| {
+            report_data.header_format.render(
+                &report_data.color_data,
+                kind,
+                name,
+                (line_number, column),
+                message.as_deref(),
+            )
+        };
+
+        if report_data.group_errors {
+            let header = header(None);
 
-            buffer.push_str(report_data.color_data.esc);
-            buffer.push_str(report_data.color_data.reset);
-            buffer.push('\n');
+            report_data
+                .grouped_messages
+                .push((span_start, format!("{header}{buffer}")));
+        } else {
+            let header = header(Some(name.as_str()));
+
+            report_data.error_messages.push(format!(
+                "{0}{1}{4}{2}{3}\n{header}{buffer}",
+                report_data.color_data.esc,
+                report_data.color_data.divider.to_ansi_escape(),
+                report_data.color_data.esc,
+                report_data.color_data.reset,
+                "-".repeat(report_data.divider_width),
+            ));
         }
+    }
+}
 
-        report_data.error_messages.push(buffer);
+// the diagnostic `quick` returns, which defers to its closures for everything but the span
+struct QuickReport<M, N> {
+    span: Span,
+    message: M,
+    notes: N,
+}
+
+impl<M, N> Reportable<String, String> for QuickReport<M, N>
+where
+    M: Fn() -> String,
+    N: Fn() -> Vec<String>,
+{
+    fn span(&self) -> Span {
+        self.span
+    }
+
+    fn message(&self) -> Option<String> {
+        Some((self.message)())
+    }
+
+    fn notes(&self) -> Vec<String> {
+        (self.notes)()
+    }
+}
+
+/// A one-off diagnostic at `span`, whose message and notes come from calling `message` and
+/// `notes` whenever it's reported
+pub fn quick<M, N>(span: Span, message: M, notes: N) -> impl Reportable<String, String>
+where
+    M: Fn() -> String,
+    N: Fn() -> Vec<String>,
+{
+    QuickReport {
+        span,
+        message,
+        notes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_all_of_nothing_is_none() {
+        assert_eq!(Span::merge_all([]), None);
+    }
+
+    #[test]
+    fn merge_all_of_one_span_is_that_span() {
+        assert_eq!(Span::merge_all([Span::new(3, 7)]), Some(Span::new(3, 7)));
+    }
+
+    #[test]
+    fn merge_all_covers_every_span() {
+        let spans = [Span::new(10, 12), Span::new(2, 4), Span::new(6, 15)];
+
+        assert_eq!(Span::merge_all(spans), Some(Span::new(2, 15)));
+    }
+
+    #[test]
+    fn reports_are_kept_once_whether_or_not_they_are_grouped() {
+        let source = "first line\nsecond line\n";
+        let report = |report_data: &mut ReportData<&str, &str, &str, &str>| {
+            quick(Span::new(11, 17), || "late".to_owned(), Vec::new).report(report_data);
+            quick(Span::new(0, 5), || "early".to_owned(), Vec::new).report(report_data);
+        };
+
+        let mut report_data = ReportData::new(
+            source,
+            "ERROR",
+            "test.txt",
+            "[snip]",
+            ReportColors::for_output(false),
+        );
+
+        report(&mut report_data);
+
+        assert_eq!(report_data.take_errors().len(), 2);
+        assert_eq!(report_data.take_grouped_errors(), None);
+
+        report_data.group_errors = true;
+        report(&mut report_data);
+
+        assert!(report_data.errors().is_empty());
+
+        let grouped = report_data.take_grouped_errors().unwrap();
+
+        assert!(grouped.find("early").unwrap() < grouped.find("late").unwrap());
+    }
+
+    #[test]
+    fn line_lookups_clamp_out_of_range_offsets() {
+        let source = "ab\nc\u{e9}d\n";
+
+        assert_eq!(line_start(source, 100), source.len());
+        assert_eq!(line_number(source, 100), 3);
+        assert_eq!(line_and_column(source, 100), (3, 1));
+    }
+
+    #[test]
+    fn line_lookups_clamp_offsets_inside_a_character() {
+        let source = "ab\nc\u{e9}d\n";
+        let inside = source.find('\u{e9}').unwrap() + 1;
+
+        assert_eq!(line_start(source, inside), 3);
+        assert_eq!(line_number(source, inside), 2);
+        assert_eq!(
+            line_and_column(source, inside),
+            line_and_column(source, inside - 1)
+        );
+    }
+
+    // the one report of a cost with a bad value, rendered without color after `configure`
+    fn render_cost_report<F>(configure: F) -> String
+    where
+        F: FnOnce(&mut ReportData<&str, &str, &str, &str>),
+    {
+        let source = "ship A\n\tcost 10\n\tmass 5\n";
+        let mut report_data = ReportData::new(
+            source,
+            "ERROR",
+            "ships.txt",
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        configure(&mut report_data);
+
+        quick(
+            Span::new(13, 15),
+            || "bad cost".to_owned(),
+            || vec!["costs are in credits".to_owned()],
+        )
+        .report(&mut report_data);
+
+        report_data.take_errors().concat()
+    }
+
+    const COST_SNIPPET: &str = " 1 | ship A\n 2 |     cost 10\n   |          ^^\n 3 |     mass 5\n";
+
+    #[test]
+    fn header_formats_lay_out_the_location_kind_and_message() {
+        let header = |header_format| {
+            let rendered =
+                render_cost_report(|report_data| report_data.header_format = header_format);
+
+            rendered
+                .strip_prefix("---------------\n")
+                .and_then(|rest| rest.strip_suffix("NOTE: costs are in credits\n"))
+                .and_then(|rest| rest.strip_suffix(COST_SNIPPET))
+                .unwrap()
+                .to_owned()
+        };
+
+        assert_eq!(
+            header(HeaderFormat::Split),
+            "ships.txt:2:6\nERROR: bad cost\n"
+        );
+        assert_eq!(
+            header(HeaderFormat::Bracketed),
+            "ERROR [ships.txt:2:6] bad cost\n"
+        );
+        assert_eq!(
+            header(HeaderFormat::Compiler),
+            "ships.txt:2:6: ERROR: bad cost\n"
+        );
+    }
+
+    #[test]
+    fn notes_go_before_or_after_the_snippet() {
+        let header = "---------------\nships.txt:2:6\nERROR: bad cost\n";
+        let note = "NOTE: costs are in credits\n";
+
+        assert_eq!(
+            render_cost_report(|report_data| report_data.notes_position = NotesPosition::After),
+            format!("{header}{COST_SNIPPET}{note}")
+        );
+        assert_eq!(
+            render_cost_report(|report_data| report_data.notes_position = NotesPosition::Before),
+            format!("{header}{note}{COST_SNIPPET}")
+        );
+    }
+
+    #[test]
+    fn the_divider_is_as_wide_as_configured() {
+        let rendered = render_cost_report(|report_data| report_data.divider_width = 40);
+
+        assert_eq!(rendered.lines().next(), Some("-".repeat(40).as_str()));
+        assert!(rendered.starts_with(&format!("{}\nships.txt:2:6\n", "-".repeat(40))));
+
+        let default = render_cost_report(|_report_data| {});
+
+        assert_eq!(default.lines().next(), Some("-".repeat(15).as_str()));
+    }
+
+    #[test]
+    fn context_lines_stop_at_the_start_and_end_of_the_file() {
+        let source = "a 1\nb 2\n\nc 3\nd 4\ne 5\n";
+
+        let snippet = |context_lines| {
+            let mut report_data = ReportData::new(
+                source,
+                "ERROR",
+                "x.txt",
+                "[snip]",
+                ReportColors::colorless(),
+            );
+            report_data.context_lines = context_lines;
+
+            quick(Span::new(11, 12), String::new, Vec::new).render_snippet(&report_data)
+        };
+
+        assert_eq!(snippet(0), " 4 | c 3\n   |   ^\n");
+        assert_eq!(snippet(1), " 2 | b 2\n 4 | c 3\n   |   ^\n 5 | d 4\n");
+        assert_eq!(
+            snippet(3),
+            " 1 | a 1\n 2 | b 2\n 4 | c 3\n   |   ^\n 5 | d 4\n 6 | e 5\n"
+        );
+    }
+
+    #[test]
+    fn render_snippet_is_only_the_source_lines_and_carets() {
+        let cost_report_data = ReportData::new(
+            "ship A\n\tcost 10\n\tmass 5\n",
+            "ERROR",
+            "ships.txt",
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        assert_eq!(
+            quick(Span::new(13, 15), String::new, Vec::new).render_snippet(&cost_report_data),
+            COST_SNIPPET
+        );
+        assert!(render_cost_report(|_report_data| {}).contains(COST_SNIPPET));
+
+        let source = "ship \u{e9}t\u{e9} 10\n";
+        let report_data = ReportData::new(
+            source,
+            "ERROR",
+            "x.txt",
+            "[snip]",
+            ReportColors::colorless(),
+        );
+
+        let snippet = |span| quick(span, String::new, Vec::new).render_snippet(&report_data);
+
+        assert_eq!(
+            snippet(Span::new(5, 10)),
+            " 1 | ship \u{e9}t\u{e9} 10\n   |      ^^^\n"
+        );
+        assert_eq!(
+            snippet(Span::new(11, 13)),
+            " 1 | ship \u{e9}t\u{e9} 10\n   |          ^^\n"
+        );
+    }
+
+    #[test]
+    fn diff_reports_marks_removed_added_and_unchanged_lines() {
+        let expected = render_cost_report(|_report_data| {});
+        let actual = expected.replace("bad cost", "odd cost") + "NOTE: another note\n";
+
+        assert_eq!(
+            diff_reports(&expected, &actual, ReportColors::colorless()),
+            concat!(
+                "  ---------------\n",
+                "  ships.txt:2:6\n",
+                "- ERROR: bad cost\n",
+                "+ ERROR: odd cost\n",
+                "   1 | ship A\n",
+                "   2 |     cost 10\n",
+                "     |          ^^\n",
+                "   3 |     mass 5\n",
+                "  NOTE: costs are in credits\n",
+                "+ NOTE: another note\n",
+            )
+        );
+
+        let colors = ReportColors::default();
+        let diff = diff_reports("same\nold\n", "same\nnew\n", colors);
+
+        assert_eq!(
+            diff,
+            format!(
+                "{ESC}{none}  same{ESC}{RESET}\n{ESC}{removed}- old{ESC}{RESET}\n{ESC}{added}+ new{ESC}{RESET}\n",
+                none = ReportColor::None.to_ansi_escape(),
+                removed = colors.message.to_ansi_escape(),
+                added = colors.note.to_ansi_escape(),
+            )
+        );
     }
 }